@@ -0,0 +1,27 @@
+// Directional light and Blinn-Phong shading with the material of the mesh
+
+uniform layout(location=8) vec3 camera_position;
+
+// Material of the mesh, from its MTL file
+uniform layout(location=9) vec3 material_ambient;
+uniform layout(location=10) vec3 material_diffuse;
+uniform layout(location=11) vec3 material_specular;
+uniform layout(location=12) float material_shininess;
+
+vec3 light_direction = normalize(vec3(0.8, -0.5, 0.6));
+
+// Light leaving a point with the given unit normal towards the camera. Albedo scales the ambient and diffuse light,
+// and diffuse_color the diffuse light alone
+vec3 blinn_phong(vec3 normal, vec3 position, vec3 albedo, vec3 diffuse_color)
+{
+    float diffuse = max(0, dot(normal, -light_direction));
+
+    // Blinn-Phong highlight, only on surfaces facing the light
+    vec3 view_direction = normalize(camera_position - position);
+    vec3 half_direction = normalize(view_direction - light_direction);
+    float specular = diffuse > 0.0 ? pow(max(0.0, dot(normal, half_direction)), material_shininess) : 0.0;
+
+    return material_ambient * albedo
+        + diffuse_color * albedo * material_diffuse * diffuse
+        + material_specular * specular;
+}
@@ -2,16 +2,29 @@
 
 in vec4 vertex_color;
 in vec3 vertex_normal;
+in vec3 vertex_position;
+in vec2 vertex_texcoord;
 
 out vec4 frag_color;
 
-vec3 light_direction = normalize(vec3(0.8, -0.5, 0.6));
+#include "lighting.glsl"
+
+// Diffuse texture, bound to texture unit 0 only when the mesh has one
+uniform layout(location=13) int has_diffuse_texture;
+layout(binding=0) uniform sampler2D diffuse_texture;
 
 void main()
 {
-    // Task 1c
-    // frag_color = vec4(vertex_normal, 1.0f);
+    // Interpolation between vertices shortens the normal
+    vec3 normal = normalize(vertex_normal);
 
+#ifdef SHOW_NORMALS
+    // Task 1c
+    frag_color = vec4(normal, 1.0f);
+#else
     // Task 1d
-    frag_color = vec4(vertex_color.xyz * max(0, dot(vertex_normal, -light_direction)), vertex_color.w);
+    vec3 albedo = has_diffuse_texture != 0 ? texture(diffuse_texture, vertex_texcoord).rgb : vec3(1.0);
+    vec3 color = blinn_phong(normal, vertex_position, albedo, vertex_color.xyz);
+    frag_color = vec4(color, vertex_color.w);
+#endif
 }
\ No newline at end of file
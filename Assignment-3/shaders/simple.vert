@@ -3,17 +3,27 @@
 in layout(location=0) vec3 position;
 in layout(location=1) vec4 color;
 in layout(location=2) vec3 normal;
+in layout(location=3) vec4 tangent;
+in layout(location=4) vec2 texcoord;
 
 out vec4 vertex_color;
 out vec3 vertex_normal;
+out vec3 vertex_position;
+out vec4 vertex_tangent;
+out vec2 vertex_texcoord;
 
 // Task 4
 uniform layout(location=5) mat4 transform_matrix;
-uniform layout(location=6) mat4 normal_transformation;
+uniform layout(location=6) mat3 normal_transformation;
+uniform layout(location=7) mat4 model_matrix;
 
 void main()
 {
     gl_Position = transform_matrix * vec4(position, 1.0f);
     vertex_color = color;
-    vertex_normal = normal;
+    vertex_normal = normalize(normal_transformation * normal);
+    vertex_position = (model_matrix * vec4(position, 1.0f)).xyz;
+    // Tangents lie in the surface, so they are transformed like positions rather than like normals
+    vertex_tangent = vec4(normalize(mat3(model_matrix) * tangent.xyz), tangent.w);
+    vertex_texcoord = texcoord;
 }
\ No newline at end of file
@@ -0,0 +1,213 @@
+extern crate nalgebra_glm as glm;
+
+// Axis-aligned bounding box. Empty boxes have min above max, so that growing them by a point gives that point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: glm::vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<I>(points: I) -> Aabb
+        where I: IntoIterator<Item = glm::Vec3>
+    {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.grow(&point);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&mut self, point: &glm::Vec3) {
+        self.min = glm::min2(&self.min, point);
+        self.max = glm::max2(&self.max, point);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: glm::min2(&self.min, &other.min), max: glm::max2(&self.max, &other.max) }
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    // Half the size of the box along each axis
+    pub fn half_extents(&self) -> glm::Vec3 {
+        (self.max - self.min) / 2.0
+    }
+
+    #[cfg(test)]
+    pub fn corners(&self) -> [glm::Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            glm::vec3(a.x, a.y, a.z), glm::vec3(b.x, a.y, a.z), glm::vec3(a.x, b.y, a.z), glm::vec3(b.x, b.y, a.z),
+            glm::vec3(a.x, a.y, b.z), glm::vec3(b.x, a.y, b.z), glm::vec3(a.x, b.y, b.z), glm::vec3(b.x, b.y, b.z),
+        ]
+    }
+
+    // Smallest axis-aligned box around this box after transforming it. Each axis of the new box gets the
+    // absolute contribution of every axis of the old one, which is the same as transforming all eight corners.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = (matrix * glm::vec4(self.center().x, self.center().y, self.center().z, 1.0)).xyz();
+        let linear = glm::mat4_to_mat3(matrix);
+        let absolute = linear.map(|value| value.abs());
+        let half_extents = absolute * self.half_extents();
+        Aabb { min: center - half_extents, max: center + half_extents }
+    }
+}
+
+// Empty spheres have a negative radius
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn empty() -> BoundingSphere {
+        BoundingSphere { center: glm::zero(), radius: -1.0 }
+    }
+
+    // Centered on the bounding box of the points, which is not the smallest sphere, but close and cheap
+    pub fn from_points(points: &[glm::Vec3]) -> BoundingSphere {
+        if points.is_empty() {
+            return BoundingSphere::empty();
+        }
+        let center = Aabb::from_points(points.iter().cloned()).center();
+        let radius = points.iter().map(|p| glm::distance(p, &center)).fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    #[cfg(test)]
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        glm::distance(point, &self.center) <= self.radius
+    }
+
+    pub fn union(&self, other: &BoundingSphere) -> BoundingSphere {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let offset = other.center - self.center;
+        let distance = glm::length(&offset);
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        BoundingSphere { center, radius }
+    }
+
+    // Non-uniform scaling stretches the sphere into an ellipsoid, which is covered by scaling the radius by the
+    // largest scale factor
+    pub fn transformed(&self, matrix: &glm::Mat4) -> BoundingSphere {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = (matrix * glm::vec4(self.center.x, self.center.y, self.center.z, 1.0)).xyz();
+        let scale = (0..3)
+            .map(|column| glm::length(&matrix.column(column).xyz()))
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius: self.radius * scale }
+    }
+}
+
+// Both kinds of bounds for the same geometry. The box is tighter for long thin shapes, the sphere cheaper to test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn empty() -> Bounds {
+        Bounds { aabb: Aabb::empty(), sphere: BoundingSphere::empty() }
+    }
+
+    pub fn from_points(points: &[glm::Vec3]) -> Bounds {
+        Bounds { aabb: Aabb::from_points(points.iter().cloned()), sphere: BoundingSphere::from_points(points) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aabb.is_empty()
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds { aabb: self.aabb.union(&other.aabb), sphere: self.sphere.union(&other.sphere) }
+    }
+
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Bounds {
+        Bounds { aabb: self.aabb.transformed(matrix), sphere: self.sphere.transformed(matrix) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_vec3_eq(actual: &glm::Vec3, expected: &glm::Vec3) {
+        assert!(glm::distance(actual, expected) < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn transformed_box_covers_transformed_corners() {
+        let aabb = Aabb { min: glm::vec3(-1.0, 0.0, 2.0), max: glm::vec3(1.0, 3.0, 4.0) };
+        let matrix = glm::translation(&glm::vec3(5.0, 0.0, 0.0))
+            * glm::rotation(0.7, &glm::normalize(&glm::vec3(1.0, 2.0, 3.0)))
+            * glm::scaling(&glm::vec3(2.0, 0.5, 1.0));
+        let expected = Aabb::from_points(aabb.corners().iter().map(|c| (matrix * glm::vec4(c.x, c.y, c.z, 1.0)).xyz()));
+        let transformed = aabb.transformed(&matrix);
+        assert_vec3_eq(&transformed.min, &expected.min);
+        assert_vec3_eq(&transformed.max, &expected.max);
+        assert!(Aabb::empty().transformed(&matrix).is_empty());
+    }
+
+    #[test]
+    fn spheres_contain_their_points_after_transformation() {
+        let points = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(-1.0, 2.0, 0.0), glm::vec3(0.0, 1.0, 3.0)];
+        let sphere = BoundingSphere::from_points(&points);
+        let matrix = glm::rotation(1.2, &glm::vec3(0.0, 1.0, 0.0)) * glm::scaling(&glm::vec3(1.0, 3.0, 0.5));
+        let transformed = sphere.transformed(&matrix);
+        for p in &points {
+            assert!(sphere.contains(p));
+            assert!(transformed.contains(&(matrix * glm::vec4(p.x, p.y, p.z, 1.0)).xyz()));
+        }
+    }
+
+    #[test]
+    fn unions_cover_both_sides() {
+        let a = Bounds::from_points(&[glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 1.0)]);
+        let b = Bounds::from_points(&[glm::vec3(4.0, 0.0, 0.0), glm::vec3(5.0, 1.0, 1.0)]);
+        let union = a.union(&b);
+        assert_vec3_eq(&union.aabb.min, &glm::vec3(0.0, 0.0, 0.0));
+        assert_vec3_eq(&union.aabb.max, &glm::vec3(5.0, 1.0, 1.0));
+        for corner in a.aabb.corners().iter().chain(b.aabb.corners().iter()) {
+            assert!(union.sphere.radius >= glm::distance(corner, &union.sphere.center) - 1e-5);
+        }
+        assert_eq!(Bounds::empty().union(&a), a);
+        assert_eq!(a.union(&Bounds::empty()), a);
+    }
+}
@@ -0,0 +1,324 @@
+extern crate nalgebra_glm as glm;
+
+use crate::orientation::{self, EulerOrder};
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Closest the orbiting modes let the camera get to what it circles
+const MIN_ORBIT_DISTANCE: f32 = 1.0;
+// Looking straight up or down makes the yaw meaningless, so the pitch stops just short of it
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    // Moves freely along its own axes
+    Fly,
+    // Circles a fixed point, always looking at it
+    Orbit { target: glm::Vec3, distance: f32 },
+    // Circles the origin of a node wherever it moves
+    Follow { node: NodeId, distance: f32 },
+    // Trails behind a node on a spring. Velocity is the state of the spring
+    Chase { settings: ChaseSettings, velocity: glm::Vec3 },
+}
+
+// Where a chasing camera sits relative to the node it chases, and how it gets there
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChaseSettings {
+    pub node: NodeId,
+    // In the heading frame of the node: x to its right, y up and z behind it. The frame follows the direction the node
+    // faces along the ground, but not its pitch or roll, which would swing the camera around
+    pub offset: glm::Vec3,
+    // How far ahead of the node the camera looks
+    pub look_ahead: f32,
+    // Angular frequency of the spring, in radians per second. Higher values lag less behind the node
+    pub stiffness: f32,
+}
+
+impl ChaseSettings {
+    pub fn new(node: NodeId) -> ChaseSettings {
+        ChaseSettings { node, offset: glm::vec3(0.0, 5.0, 20.0), look_ahead: 10.0, stiffness: 4.0 }
+    }
+
+    // Where the camera wants to be and the point it looks at, from the world transformation of the node
+    fn goal(&self, scene: &SceneGraph) -> Option<(glm::Vec3, glm::Vec3)> {
+        let matrix = scene.get(self.node)?.current_transformation_matrix;
+        let position = matrix.column(3).xyz();
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        let facing = -matrix.column(2).xyz();
+        // A node facing straight up or down has no heading, so any will do
+        let forward = if glm::length(&facing.xz()) > 1e-6 {
+            glm::normalize(&glm::vec3(facing.x, 0.0, facing.z))
+        } else {
+            glm::vec3(0.0, 0.0, -1.0)
+        };
+        let right = glm::cross(&forward, &up);
+        let camera_position = position + right * self.offset.x + up * self.offset.y - forward * self.offset.z;
+        Some((camera_position, position + forward * self.look_ahead))
+    }
+}
+
+// What the user asked the camera to do since the last frame, already scaled by the time it took
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraInput {
+    // Along the right, up and forward axes of the camera
+    pub movement: glm::Vec3,
+    // Yaw to the left and pitch upwards, in radians
+    pub rotation: glm::Vec2,
+    // Towards what the camera looks at
+    pub zoom: f32,
+}
+
+impl CameraInput {
+    pub fn none() -> CameraInput {
+        CameraInput { movement: glm::zero(), rotation: glm::zero(), zoom: 0.0 }
+    }
+}
+
+// Looks down -z when yaw and pitch are zero. There is no roll, so the camera never tips over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: glm::Vec3,
+    // Turn to the left about the world y axis
+    pub yaw: f32,
+    // Turn upwards about the x axis of the camera, after yawing
+    pub pitch: f32,
+    // Vertical field of view, in radians
+    pub fov_y: f32,
+    pub aspect_ratio: f32,
+    pub near: f32,
+    pub far: f32,
+    pub mode: CameraMode,
+}
+
+impl Camera {
+    pub fn new(position: glm::Vec3, aspect_ratio: f32) -> Camera {
+        Camera {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov_y: 1.0,
+            aspect_ratio,
+            near: 1.0,
+            far: 1000.0,
+            mode: CameraMode::Fly,
+        }
+    }
+
+    pub fn orientation(&self) -> glm::Quat {
+        orientation::from_euler(&glm::vec3(self.pitch, self.yaw, 0.0), EulerOrder::YXZ)
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation(), &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation(), &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation(), &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // Inverse of the placement of the camera in the world, so the camera ends up at the origin looking down -z
+    pub fn view_matrix(&self) -> glm::Mat4 {
+        glm::quat_to_mat4(&glm::quat_conjugate(&self.orientation())) * glm::translation(&-self.position)
+    }
+
+    pub fn projection_matrix(&self) -> glm::Mat4 {
+        glm::perspective(self.aspect_ratio, self.fov_y, self.near, self.far)
+    }
+
+    pub fn view_projection_matrix(&self) -> glm::Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    // Turns the camera towards target without moving it. Targets straight above or below end up just short of it
+    pub fn look_at(&mut self, target: &glm::Vec3) {
+        let direction = target - self.position;
+        if glm::length(&direction) < 1e-6 {
+            return;
+        }
+        self.yaw = (-direction.x).atan2(-direction.z);
+        self.pitch = direction.y.atan2(glm::length(&direction.xz())).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn fly(&mut self) {
+        self.mode = CameraMode::Fly;
+    }
+
+    // Starts circling target from where the camera is now
+    pub fn orbit(&mut self, target: glm::Vec3) {
+        let distance = glm::distance(&self.position, &target).max(MIN_ORBIT_DISTANCE);
+        self.mode = CameraMode::Orbit { target, distance };
+        self.look_at(&target);
+    }
+
+    // Starts circling node from where the camera is now. Uses the world transformation of the node from the last
+    // `update_node_transformations`
+    pub fn follow(&mut self, scene: &SceneGraph, node: NodeId) {
+        let target = node_position(scene, node).unwrap_or(self.position);
+        let distance = glm::distance(&self.position, &target).max(MIN_ORBIT_DISTANCE);
+        self.mode = CameraMode::Follow { node, distance };
+        self.look_at(&target);
+    }
+
+    // Starts chasing the node of settings from right where the spring would settle
+    pub fn chase(&mut self, scene: &SceneGraph, settings: ChaseSettings) {
+        if let Some((position, look_target)) = settings.goal(scene) {
+            self.position = position;
+            self.look_at(&look_target);
+        }
+        self.mode = CameraMode::Chase { settings, velocity: glm::zero() };
+    }
+
+    // Applies one frame of input, delta_time seconds after the last. Call it after `update_node_transformations`,
+    // so followed nodes are where they will be drawn. The orbiting modes only use the forward part of the movement,
+    // to move closer, and chasing cameras ignore input altogether.
+    pub fn update(&mut self, input: &CameraInput, scene: &SceneGraph, delta_time: f32) {
+        self.yaw += input.rotation.x;
+        self.pitch = (self.pitch + input.rotation.y).clamp(-MAX_PITCH, MAX_PITCH);
+        let closer = input.movement.z + input.zoom;
+
+        let (target, distance) = match &mut self.mode {
+            CameraMode::Fly => {
+                let movement = self.right() * input.movement.x + self.up() * input.movement.y + self.forward() * closer;
+                self.position += movement;
+                return;
+            },
+            CameraMode::Orbit { target, distance } => (*target, distance),
+            CameraMode::Follow { node, distance } => match node_position(scene, *node) {
+                Some(target) => (target, distance),
+                None => return,
+            },
+            CameraMode::Chase { .. } => {
+                self.update_chase(scene, delta_time);
+                return;
+            },
+        };
+        *distance = (*distance - closer).max(MIN_ORBIT_DISTANCE);
+        let distance = *distance;
+        self.position = target - self.forward() * distance;
+    }
+
+    // Critically damped spring towards the goal, solved exactly over the frame so that long frames stay stable
+    fn update_chase(&mut self, scene: &SceneGraph, delta_time: f32) {
+        if let CameraMode::Chase { settings, velocity } = &mut self.mode {
+            if let Some((goal, look_target)) = settings.goal(scene) {
+                let omega = settings.stiffness;
+                let offset = self.position - goal;
+                let change = (*velocity + offset * omega) * delta_time;
+                let decay = (-omega * delta_time).exp();
+                *velocity = (*velocity - change * omega) * decay;
+                self.position = goal + (offset + change) * decay;
+                self.look_at(&look_target);
+            }
+        }
+    }
+}
+
+fn node_position(scene: &SceneGraph, node: NodeId) -> Option<glm::Vec3> {
+    scene.get(node).map(|node| node.current_transformation_matrix.column(3).xyz())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::{update_node_transformations, SceneNode};
+
+    fn assert_vec3_eq(actual: &glm::Vec3, expected: &glm::Vec3) {
+        assert!(glm::distance(actual, expected) < 1e-4, "expected {}, got {}", expected, actual);
+    }
+
+    fn transform_point(matrix: &glm::Mat4, point: &glm::Vec3) -> glm::Vec3 {
+        let transformed = matrix * glm::vec4(point.x, point.y, point.z, 1.0);
+        transformed.xyz() / transformed.w
+    }
+
+    #[test]
+    fn view_matrix_puts_the_camera_at_the_origin_looking_down_z() {
+        let mut camera = Camera::new(glm::vec3(3.0, 2.0, 5.0), 4.0 / 3.0);
+        camera.look_at(&glm::vec3(-1.0, 4.0, 1.0));
+        let view = camera.view_matrix();
+        assert_vec3_eq(&transform_point(&view, &camera.position), &glm::zero());
+        let ahead = transform_point(&view, &glm::vec3(-1.0, 4.0, 1.0));
+        assert_vec3_eq(&glm::normalize(&ahead), &glm::vec3(0.0, 0.0, -1.0));
+        assert_vec3_eq(&transform_point(&view, &(camera.position + camera.up())), &glm::vec3(0.0, 1.0, 0.0));
+
+        // The same as looking at the target the usual way, and projected with the settings of the camera
+        let expected = glm::look_at(&camera.position, &glm::vec3(-1.0, 4.0, 1.0), &glm::vec3(0.0, 1.0, 0.0));
+        assert!((view - expected).abs().max() < 1e-4);
+        assert_eq!(camera.projection_matrix(), glm::perspective(4.0 / 3.0, 1.0, 1.0, 1000.0));
+    }
+
+    #[test]
+    fn flying_moves_along_the_camera_axes() {
+        let mut camera = Camera::new(glm::zero(), 1.0);
+        let scene = SceneGraph::new();
+        // A quarter turn to the left looks down -x
+        let turn = CameraInput { rotation: glm::vec2(std::f32::consts::FRAC_PI_2, 0.0), ..CameraInput::none() };
+        camera.update(&turn, &scene, 0.1);
+        assert_vec3_eq(&camera.forward(), &glm::vec3(-1.0, 0.0, 0.0));
+        let forward_and_up = CameraInput { movement: glm::vec3(0.0, 1.0, 2.0), ..CameraInput::none() };
+        camera.update(&forward_and_up, &scene, 0.1);
+        assert_vec3_eq(&camera.position, &glm::vec3(-2.0, 1.0, 0.0));
+
+        // Pitch stops short of straight up
+        camera.update(&CameraInput { rotation: glm::vec2(0.0, 10.0), ..CameraInput::none() }, &scene, 0.1);
+        assert!(camera.pitch < std::f32::consts::FRAC_PI_2 && camera.forward().y > 0.99);
+    }
+
+    #[test]
+    fn orbiting_keeps_looking_at_the_target() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let node = scene.add_node(SceneNode::new());
+        scene.add_child(root, node);
+        scene[node].set_position(glm::vec3(10.0, 0.0, 0.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        let mut camera = Camera::new(glm::vec3(0.0, 0.0, 10.0), 1.0);
+        camera.orbit(glm::zero());
+        let circle = CameraInput { rotation: glm::vec2(1.0, 0.5), movement: glm::vec3(0.0, 0.0, 2.0), zoom: 1.0 };
+        camera.update(&circle, &scene, 0.1);
+        assert!((glm::length(&camera.position) - 7.0).abs() < 1e-4);
+        assert_vec3_eq(&glm::normalize(&-camera.position), &camera.forward());
+
+        // Following a node keeps the distance as it moves
+        camera.follow(&scene, node);
+        assert_eq!(camera.mode, CameraMode::Follow { node, distance: glm::distance(&camera.position, &glm::vec3(10.0, 0.0, 0.0)) });
+        scene[node].set_position(glm::vec3(10.0, 0.0, -20.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+        camera.update(&CameraInput::none(), &scene, 0.1);
+        let target = glm::vec3(10.0, 0.0, -20.0);
+        assert_vec3_eq(&(target - camera.forward() * glm::distance(&camera.position, &target)), &camera.position);
+        assert!(matches!(camera.mode, CameraMode::Follow { distance, .. } if (distance - glm::distance(&camera.position, &target)).abs() < 1e-4));
+    }
+
+    #[test]
+    fn chasing_trails_behind_the_heading_of_the_node() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let node = scene.add_node(SceneNode::new());
+        scene.add_child(root, node);
+        // Facing +x, and rolled over, which the camera should not care about
+        scene[node].set_rotation_euler(glm::vec3(0.0, -std::f32::consts::FRAC_PI_2, 0.8), EulerOrder::YXZ);
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        let settings = ChaseSettings { node, offset: glm::vec3(0.0, 2.0, 10.0), look_ahead: 5.0, stiffness: 4.0 };
+        let mut camera = Camera::new(glm::zero(), 1.0);
+        camera.chase(&scene, settings);
+        assert_vec3_eq(&camera.position, &glm::vec3(-10.0, 2.0, 0.0));
+        assert_vec3_eq(&camera.forward(), &glm::normalize(&glm::vec3(15.0, -2.0, 0.0)));
+
+        // The camera lags behind a jump, without passing the goal, and settles there
+        scene[node].set_position(glm::vec3(10.0, 0.0, 0.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+        camera.update(&CameraInput::none(), &scene, 0.1);
+        assert!(camera.position.x > -10.0 && camera.position.x < 0.0);
+        for _ in 0..100 {
+            camera.update(&CameraInput::none(), &scene, 0.1);
+            assert!(camera.position.x <= 1e-4);
+        }
+        assert_vec3_eq(&camera.position, &glm::vec3(0.0, 2.0, 0.0));
+    }
+}
@@ -0,0 +1,253 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bounds::{Aabb, Bounds, BoundingSphere};
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Points p with dot(normal, p) + distance >= 0 are on the inside. The normal is unit length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: glm::Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(coefficients: glm::Vec4) -> Plane {
+        let length = glm::length(&coefficients.xyz());
+        Plane { normal: coefficients.xyz() / length, distance: coefficients.w / length }
+    }
+
+    pub fn signed_distance(&self, point: &glm::Vec3) -> f32 {
+        glm::dot(&self.normal, point) + self.distance
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    // Left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Planes of the volume that the view-projection matrix maps onto the OpenGL clip cube, in world space.
+    // A point is inside when -w <= x, y, z <= w after projection, and each of those inequalities is a plane
+    // made of the last row of the matrix plus or minus one of the others.
+    pub fn from_matrix(view_projection: &glm::Mat4) -> Frustum {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum {
+            planes: [
+                Plane::from_coefficients(w + x),
+                Plane::from_coefficients(w - x),
+                Plane::from_coefficients(w + y),
+                Plane::from_coefficients(w - y),
+                Plane::from_coefficients(w + z),
+                Plane::from_coefficients(w - z),
+            ],
+        }
+    }
+
+    // Empty bounds say nothing about where the geometry is, so it might be anywhere
+    pub fn classify_sphere(&self, sphere: &BoundingSphere) -> Visibility {
+        if sphere.is_empty() {
+            return Visibility::Intersecting;
+        }
+        let mut visibility = Visibility::Inside;
+        for plane in &self.planes {
+            let distance = plane.signed_distance(&sphere.center);
+            if distance < -sphere.radius {
+                return Visibility::Outside;
+            }
+            if distance < sphere.radius {
+                visibility = Visibility::Intersecting;
+            }
+        }
+        visibility
+    }
+
+    // Boxes near the corners of the frustum can be outside it without being fully behind any single plane.
+    // Those are reported as intersecting, which only costs drawing something that was not needed.
+    pub fn classify_aabb(&self, aabb: &Aabb) -> Visibility {
+        if aabb.is_empty() {
+            return Visibility::Intersecting;
+        }
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+        let mut visibility = Visibility::Inside;
+        for plane in &self.planes {
+            let distance = plane.signed_distance(&center);
+            // How far the box reaches along the normal of the plane
+            let reach = glm::dot(&glm::abs(&plane.normal), &half_extents);
+            if distance < -reach {
+                return Visibility::Outside;
+            }
+            if distance < reach {
+                visibility = Visibility::Intersecting;
+            }
+        }
+        visibility
+    }
+
+    // The sphere is the cheaper test, the box settles what the sphere cannot
+    pub fn classify(&self, bounds: &Bounds) -> Visibility {
+        match self.classify_sphere(&bounds.sphere) {
+            Visibility::Intersecting => self.classify_aabb(&bounds.aabb),
+            visibility => visibility,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CullResult {
+    // Drawable nodes within the frustum, in the order the scene graph would have drawn them
+    pub visible: Vec<NodeId>,
+    // Number of drawable nodes that were left out
+    pub culled: usize,
+}
+
+impl CullResult {
+    pub fn drawn(&self) -> usize {
+        self.visible.len()
+    }
+}
+
+// Walks the scene from node_id, skipping whole subtrees whose bounds are outside the frustum and testing no further
+// below subtrees that are entirely inside it. Uses the world space bounds from the last `update_node_transformations`.
+// Drawable nodes without local bounds are never culled, and neither are the subtrees they are in.
+pub fn cull_scene(scene: &SceneGraph, node_id: NodeId, frustum: &Frustum) -> CullResult {
+    let mut result = CullResult::default();
+    cull_node(scene, node_id, frustum, false, &mut result);
+    result
+}
+
+fn cull_node(scene: &SceneGraph, node_id: NodeId, frustum: &Frustum, inside: bool, result: &mut CullResult) {
+    let node = &scene[node_id];
+    let mut inside = inside;
+    if !inside {
+        match frustum.classify(&node.subtree_bounds()) {
+            Visibility::Outside if node.subtree_is_bounded() => {
+                result.culled += count_drawable(scene, node_id);
+                return;
+            },
+            Visibility::Inside => inside = true,
+            Visibility::Outside | Visibility::Intersecting => {},
+        }
+    }
+    if node.index_count > 0 {
+        if inside || frustum.classify(&node.world_bounds()) != Visibility::Outside {
+            result.visible.push(node_id);
+        } else {
+            result.culled += 1;
+        }
+    }
+    for &child in node.children() {
+        cull_node(scene, child, frustum, inside, result);
+    }
+}
+
+fn count_drawable(scene: &SceneGraph, node_id: NodeId) -> usize {
+    let node = &scene[node_id];
+    let own = if node.index_count > 0 { 1 } else { 0 };
+    own + node.children().iter().map(|&child| count_drawable(scene, child)).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::{update_node_transformations, SceneNode};
+
+    // Camera at the origin looking down -z, seeing from 1 to 100 units ahead
+    fn camera_frustum() -> Frustum {
+        Frustum::from_matrix(&glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0))
+    }
+
+    fn cube_at(center: glm::Vec3, half_size: f32) -> Bounds {
+        let half = glm::vec3(half_size, half_size, half_size);
+        Bounds::from_points(&[center - half, center + half])
+    }
+
+    #[test]
+    fn planes_face_into_the_frustum() {
+        let frustum = camera_frustum();
+        for plane in &frustum.planes {
+            assert!(plane.signed_distance(&glm::vec3(0.0, 0.0, -10.0)) > 0.0);
+        }
+        // Near and far planes
+        assert!((frustum.planes[4].signed_distance(&glm::vec3(0.0, 0.0, -1.0))).abs() < 1e-4);
+        assert!((frustum.planes[5].signed_distance(&glm::vec3(0.0, 0.0, -100.0))).abs() < 1e-2);
+        // A 90 degree field of view puts the side planes at 45 degrees
+        assert!((frustum.planes[1].signed_distance(&glm::vec3(10.0, 0.0, -10.0))).abs() < 1e-4);
+    }
+
+    #[test]
+    fn bounds_are_classified_against_all_planes() {
+        let frustum = camera_frustum();
+        assert_eq!(frustum.classify(&cube_at(glm::vec3(0.0, 0.0, -10.0), 1.0)), Visibility::Inside);
+        assert_eq!(frustum.classify(&cube_at(glm::vec3(0.0, 0.0, 10.0), 1.0)), Visibility::Outside);
+        assert_eq!(frustum.classify(&cube_at(glm::vec3(0.0, 0.0, -200.0), 1.0)), Visibility::Outside);
+        assert_eq!(frustum.classify(&cube_at(glm::vec3(20.0, 0.0, -10.0), 1.0)), Visibility::Outside);
+        assert_eq!(frustum.classify(&cube_at(glm::vec3(10.0, 0.0, -10.0), 1.0)), Visibility::Intersecting);
+        assert_eq!(frustum.classify(&Bounds::empty()), Visibility::Intersecting);
+    }
+
+    #[test]
+    fn subtrees_outside_the_frustum_are_culled_whole() {
+        let frustum = camera_frustum();
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let add_drawable = |scene: &mut SceneGraph, parent: NodeId, position: glm::Vec3| {
+            let mut node = SceneNode::from_vao(1, 3);
+            node.set_local_bounds(cube_at(glm::zero(), 1.0));
+            node.set_position(position);
+            let id = scene.add_node(node);
+            scene.add_child(parent, id);
+            id
+        };
+        // A group behind the camera, with children that are behind it too
+        let behind = add_drawable(&mut scene, root, glm::vec3(0.0, 0.0, 20.0));
+        add_drawable(&mut scene, behind, glm::vec3(1.0, 0.0, 0.0));
+        add_drawable(&mut scene, behind, glm::vec3(-1.0, 0.0, 0.0));
+        // One in front of the camera, with one child that reaches into view and one that is off to the side
+        let ahead = add_drawable(&mut scene, root, glm::vec3(0.0, 0.0, -20.0));
+        let reaching = add_drawable(&mut scene, ahead, glm::vec3(0.0, 0.0, -5.0));
+        add_drawable(&mut scene, ahead, glm::vec3(100.0, 0.0, 0.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        let result = cull_scene(&scene, root, &frustum);
+        assert_eq!(result.visible, vec![ahead, reaching]);
+        assert_eq!(result.drawn(), 2);
+        assert_eq!(result.culled, 4);
+
+        // Everything is visible from far enough away
+        let overview = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 1000.0)
+            * glm::translation(&glm::vec3(0.0, 0.0, -300.0));
+        let result = cull_scene(&scene, root, &Frustum::from_matrix(&overview));
+        assert_eq!((result.drawn(), result.culled), (6, 0));
+    }
+
+    #[test]
+    fn drawable_nodes_without_bounds_are_never_culled() {
+        let frustum = camera_frustum();
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        // A bounded group behind the camera, holding a child whose bounds were never set
+        let mut group = SceneNode::from_vao(1, 3);
+        group.set_local_bounds(cube_at(glm::zero(), 1.0));
+        group.set_position(glm::vec3(0.0, 0.0, 20.0));
+        let group = scene.add_node(group);
+        let unbounded = scene.add_node(SceneNode::from_vao(1, 3));
+        scene.add_child(root, group);
+        scene.add_child(group, unbounded);
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        let result = cull_scene(&scene, root, &frustum);
+        assert_eq!(result.visible, vec![unbounded]);
+        assert_eq!(result.culled, 1);
+    }
+}
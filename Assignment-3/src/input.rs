@@ -0,0 +1,94 @@
+extern crate nalgebra_glm as glm;
+
+use glutin::event::{ElementState, MouseButton, MouseScrollDelta};
+
+// Touchpads scroll in pixels rather than lines. This many of them count as one line
+const PIXELS_PER_LINE: f32 = 20.0;
+
+// Mouse input shared between the event loop and the render thread, next to the pressed keys. Motion and scrolling
+// add up over however many events arrive between two frames, until the render thread takes them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MouseState {
+    // Where the cursor is, in physical pixels from the top left corner of the window
+    pub position: glm::Vec2,
+    pub pressed_buttons: Vec<MouseButton>,
+    motion: glm::Vec2,
+    scroll: f32,
+}
+
+// What the mouse did since the render thread last asked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MouseMotion {
+    // Raw device movement, right and down are positive. It keeps coming when the cursor is grabbed and stuck in place
+    pub delta: glm::Vec2,
+    // Lines scrolled away from the user
+    pub scroll: f32,
+}
+
+impl MouseState {
+    pub fn new() -> MouseState {
+        MouseState { position: glm::zero(), pressed_buttons: Vec::new(), motion: glm::zero(), scroll: 0.0 }
+    }
+
+    pub fn cursor_moved(&mut self, x: f64, y: f64) {
+        self.position = glm::vec2(x as f32, y as f32);
+    }
+
+    pub fn moved(&mut self, dx: f64, dy: f64) {
+        self.motion += glm::vec2(dx as f32, dy as f32);
+    }
+
+    pub fn scrolled(&mut self, delta: MouseScrollDelta) {
+        self.scroll += match delta {
+            MouseScrollDelta::LineDelta(_, lines) => lines,
+            MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / PIXELS_PER_LINE,
+        };
+    }
+
+    pub fn button(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if !self.pressed_buttons.contains(&button) {
+                    self.pressed_buttons.push(button);
+                }
+            },
+            ElementState::Released => self.pressed_buttons.retain(|&b| b != button),
+        }
+    }
+
+    pub fn is_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    // Motion and scrolling since the last call
+    pub fn take_motion(&mut self) -> MouseMotion {
+        let motion = MouseMotion { delta: self.motion, scroll: self.scroll };
+        self.motion = glm::zero();
+        self.scroll = 0.0;
+        motion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glutin::dpi::LogicalPosition;
+
+    #[test]
+    fn motion_adds_up_until_taken() {
+        let mut mouse = MouseState::new();
+        mouse.moved(3.0, -1.0);
+        mouse.moved(2.0, 4.0);
+        mouse.scrolled(MouseScrollDelta::LineDelta(0.0, 1.0));
+        mouse.scrolled(MouseScrollDelta::PixelDelta(LogicalPosition::new(0.0, -10.0)));
+        mouse.button(MouseButton::Left, ElementState::Pressed);
+        mouse.button(MouseButton::Left, ElementState::Pressed);
+        assert_eq!(mouse.take_motion(), MouseMotion { delta: glm::vec2(5.0, 3.0), scroll: 0.5 });
+        assert_eq!(mouse.take_motion(), MouseMotion { delta: glm::zero(), scroll: 0.0 });
+
+        // Buttons stay down until released, however many presses were seen
+        assert!(mouse.is_pressed(MouseButton::Left));
+        mouse.button(MouseButton::Left, ElementState::Released);
+        assert!(!mouse.is_pressed(MouseButton::Left));
+    }
+}
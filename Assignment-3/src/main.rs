@@ -1,48 +1,76 @@
 extern crate nalgebra_glm as glm;
-use gl::types::*;
 use std::{
     mem,
     ptr,
-    str,
     os::raw::c_void,
 };
 use std::thread;
+use std::collections::HashMap;
 use std::sync::{Mutex, Arc, RwLock};
 
 mod shader;
 mod util;
 mod mesh;
 mod scene_graph;
-
-use glutin::event::{Event, WindowEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+mod orientation;
+mod toolbox;
+mod scene_file;
+mod texture;
+mod bounds;
+mod frustum;
+mod picking;
+mod camera;
+mod input;
+
+use scene_graph::{SceneGraph, NodeId, update_node_transformations};
+use scene_file::{SceneDescription, UploadedMesh};
+use orientation::EulerOrder;
+use texture::{TextureCache, TextureSettings};
+use frustum::Frustum;
+use picking::Ray;
+use camera::{Camera, CameraInput, CameraMode, ChaseSettings};
+use input::MouseState;
+use shader::{Defines, ShaderVariants};
+
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, MouseButton, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
 
 const SCREEN_W: u32 = 800;
 const SCREEN_H: u32 = 600;
+// Height the helicopter keeps above the terrain
+const HELI_ALTITUDE: f32 = 5.0;
+// Camera speed in units per second, and turning speed in radians per second
+const CAMERA_SPEED: f32 = 20.0;
+const CAMERA_TURN_SPEED: f32 = 1.0;
+// Radians the camera turns per unit of mouse movement, and units it moves closer per line scrolled
+const MOUSE_SENSITIVITY: f32 = 0.003;
+const ZOOM_PER_LINE: f32 = 2.0;
 
 // Helper functions to make interacting with OpenGL a little bit prettier. You will need these!
 // The names should be pretty self explanatory
 fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
 fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
+    val.as_ptr() as *const c_void
 }
 
 // Get the size of the given type in bytes
+#[allow(dead_code)]
 fn size_of<T>() -> i32 {
     mem::size_of::<T>() as i32
 }
 
 // Get an offset in bytes for n units of type T
+#[allow(dead_code)]
 fn offset<T>(n: u32) -> *const c_void {
     (n * mem::size_of::<T>() as u32) as *const T as *const c_void
 }
 
 // == // Modify and complete the function below for the first task
-unsafe fn set_up_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>, normals: &Vec<f32>) -> u32 {
+unsafe fn set_up_vao(vertices: &[f32], indices: &[u32], colors: &[f32], normals: &[f32], tangents: &[f32], texcoords: &[f32]) -> u32 {
     let mut vao_id: u32 = 0;
     gl::GenVertexArrays(1, &mut vao_id);
     gl::BindVertexArray(vao_id);
@@ -96,78 +124,61 @@ unsafe fn set_up_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>,
     gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
     gl::EnableVertexAttribArray(2);
 
-    return vao_id;
-}
-
-unsafe fn draw_scene(root: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4) {
-    // Check if node is drawable, set uniforms, draw
-    if (root.index_count > 0) {
-        gl::UniformMatrix4fv(5, 1, 0, (view_projection_matrix * root.current_transformation_matrix).as_ptr());
-        gl::UniformMatrix4fv(6, 1, 0, (root.current_transformation_matrix).as_ptr());
-        gl::BindVertexArray(root.vao_id);
-        gl::DrawElements(gl::TRIANGLES, root.index_count, gl::UNSIGNED_INT, ptr::null());
+    // Meshes without tangents leave the attribute disabled, so the shader reads a constant (0, 0, 0, 1)
+    if !tangents.is_empty() {
+        let mut tangent_buffer_id: u32 = 0;
+        gl::GenBuffers(1, &mut tangent_buffer_id);
+        gl::BindBuffer(gl::ARRAY_BUFFER, tangent_buffer_id);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            byte_size_of_array(tangents),
+            pointer_to_array(tangents),
+            gl::STATIC_DRAW
+        );
+
+        gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, 0, ptr::null());
+        gl::EnableVertexAttribArray(3);
     }
 
-    // Recurse
-    for &child in &root.children {
-        draw_scene(&*child, view_projection_matrix);
+    // Meshes without texture coordinates leave the attribute disabled, so the shader reads a constant (0, 0)
+    if !texcoords.is_empty() {
+        let mut texcoord_buffer_id: u32 = 0;
+        gl::GenBuffers(1, &mut texcoord_buffer_id);
+        gl::BindBuffer(gl::ARRAY_BUFFER, texcoord_buffer_id);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            byte_size_of_array(texcoords),
+            pointer_to_array(texcoords),
+            gl::STATIC_DRAW
+        );
+
+        gl::VertexAttribPointer(4, 2, gl::FLOAT, gl::FALSE, 0, ptr::null());
+        gl::EnableVertexAttribArray(4);
     }
-}
-
-
-unsafe fn update_node_transformations(root: &mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4) {
-    // Construct the correct transformation matrix
-    let origin = glm::mat4(
-        1.0, 0.0, 0.0, root.reference_point[0],
-        0.0, 1.0, 0.0, root.reference_point[1],
-        0.0, 0.0, 1.0, root.reference_point[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotate_x = glm::mat4(
-        1.0, 0.0, 0.0, 0.0,
-        0.0, root.rotation[0].cos(), -root.rotation[0].sin(), 0.0,
-        0.0, root.rotation[0].sin(), root.rotation[0].cos(), 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotate_y = glm::mat4(
-        root.rotation[1].cos(), 0.0, root.rotation[1].sin(), 0.0,
-        0.0, 1.0, 0.0, 0.0,
-        -root.rotation[1].sin(), 0.0, root.rotation[1].cos(), 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotate_z = glm::mat4(
-        root.rotation[2].cos(), -root.rotation[2].sin(), 0.0, 0.0,
-        root.rotation[2].sin(), root.rotation[2].cos(), 0.0, 0.0,
-        0.0, 0.0, 1.0, 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let inverse_origin = glm::mat4(
-        1.0, 0.0, 0.0, -root.reference_point[0],
-        0.0, 1.0, 0.0, -root.reference_point[1],
-        0.0, 0.0, 1.0, -root.reference_point[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
 
-    let translation = glm::mat4(
-        1.0, 0.0, 0.0, root.position[0],
-        0.0, 1.0, 0.0, root.position[1],
-        0.0, 0.0, 1.0, root.position[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
-    // Update the node's transformation matrix
-    root.current_transformation_matrix = transformation_so_far * translation * origin * rotate_x * rotate_y * rotate_z * inverse_origin;
+    vao_id
+}
 
-    // Recurse
-    for &child in &root.children {
-        update_node_transformations(&mut *child,
-        &root.current_transformation_matrix);
+// Draws the nodes left after frustum culling, see `frustum::cull_scene`
+unsafe fn draw_scene(scene: &SceneGraph, visible_nodes: &[NodeId], view_projection_matrix: &glm::Mat4) {
+    for &node_id in visible_nodes {
+        let node = &scene[node_id];
+        gl::UniformMatrix4fv(5, 1, 0, (view_projection_matrix * node.current_transformation_matrix).as_ptr());
+        gl::UniformMatrix3fv(6, 1, 0, (node.current_normal_matrix).as_ptr());
+        gl::UniformMatrix4fv(7, 1, 0, (node.current_transformation_matrix).as_ptr());
+        gl::Uniform3fv(9, 1, node.material.ambient.as_ptr());
+        gl::Uniform3fv(10, 1, node.material.diffuse.as_ptr());
+        gl::Uniform3fv(11, 1, node.material.specular.as_ptr());
+        gl::Uniform1f(12, node.material.shininess);
+        gl::Uniform1i(13, (node.texture_id != 0) as i32);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, node.texture_id);
+        gl::BindVertexArray(node.vao_id);
+        gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, ptr::null());
     }
 }
 
+
 fn main() {
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
@@ -183,6 +194,9 @@ fn main() {
     let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
     // Send a copy of this vector to send to the render thread
     let pressed_keys = Arc::clone(&arc_pressed_keys);
+    // And the same for the mouse
+    let arc_mouse_state = Arc::new(Mutex::new(MouseState::new()));
+    let mouse_state = Arc::clone(&arc_mouse_state);
 
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
@@ -208,54 +222,83 @@ fn main() {
 
         // == // Set up your VAO here
 
-        let terrain = mesh::Terrain::load("./resources/lunarsurface.obj");
-        let terrain_vao = unsafe { set_up_vao(&terrain.vertices, &terrain.indices, &terrain.colors, &terrain.normals) };
-
-        let helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
-        let heli_body_vao = unsafe { set_up_vao(&helicopter.body.vertices, &helicopter.body.indices, &helicopter.body.colors, &helicopter.body.normals) };
-        let heli_main_vao = unsafe { set_up_vao(&helicopter.main_rotor.vertices, &helicopter.main_rotor.indices, &helicopter.main_rotor.colors, &helicopter.main_rotor.normals) };
-        let heli_tail_vao = unsafe { set_up_vao(&helicopter.tail_rotor.vertices, &helicopter.tail_rotor.indices, &helicopter.tail_rotor.colors, &helicopter.tail_rotor.normals) };
-        let heli_door_vao = unsafe { set_up_vao(&helicopter.door.vertices, &helicopter.door.indices, &helicopter.door.colors, &helicopter.door.normals) };
-
         // Set up scene graph
-        let mut root_node = scene_graph::SceneNode::new();
-        let mut terrain_node = scene_graph::SceneNode::from_vao(terrain_vao, terrain.index_count);
-        let mut heli_body_node = scene_graph::SceneNode::from_vao(heli_body_vao, helicopter.body.index_count);
-        let mut heli_main_node = scene_graph::SceneNode::from_vao(heli_main_vao, helicopter.main_rotor.index_count);
-        let mut heli_tail_node = scene_graph::SceneNode::from_vao(heli_tail_vao, helicopter.tail_rotor.index_count);
-        let mut heli_door_node = scene_graph::SceneNode::from_vao(heli_door_vao, helicopter.door.index_count);
-
-        heli_body_node.add_child(&heli_door_node);
-        heli_body_node.add_child(&heli_tail_node);
-        heli_body_node.add_child(&heli_main_node);
-        terrain_node.add_child(&heli_body_node);
-        root_node.add_child(&terrain_node);
-
-        heli_tail_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
+        // OBJ files are parsed once and shared between all meshes taken from them. They stay loaded for picking
+        let mut models: HashMap<String, mesh::Model> = HashMap::new();
+        let mut textures = TextureCache::new();
+        let mut scene = SceneGraph::new();
+        let description = SceneDescription::load("./scenes/lunar_helicopter.ron").unwrap_or_else(|e| panic!("{}", e));
+        let loaded_scene = description.instantiate(&mut scene, |mesh_description| {
+            if !models.contains_key(&mesh_description.path) {
+                let model = mesh::Model::load(&mesh_description.path).map_err(|e| e.to_string())?;
+                models.insert(mesh_description.path.clone(), model);
+            }
+            let model = &models[&mesh_description.path];
+            let mut mesh = model.part_or_single(mesh_description.object.as_deref()).map_err(|e| e.to_string())?.clone();
+            mesh.set_fallback_color(mesh_description.color);
+            let material = mesh.material_or_default();
+            let texture_path = mesh_description.texture.as_ref().or(material.diffuse_texture.as_ref());
+            let texture_id = match texture_path {
+                Some(path) => {
+                    if mesh.texcoords.is_empty() {
+                        mesh.generate_planar_texcoords();
+                    }
+                    unsafe { textures.get_or_load(path, &TextureSettings::default()) }.map_err(|e| e.to_string())?.id
+                },
+                None => 0,
+            };
+            let vao_id = unsafe { set_up_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals, &mesh.tangents, &mesh.texcoords) };
+            let uploaded = UploadedMesh { vao_id, index_count: mesh.index_count, material, texture_id, bounds: mesh.bounds };
+            Ok(uploaded)
+        }).unwrap_or_else(|e| panic!("{}", e));
+
+        // The helicopter is a child of the terrain node, so the terrain can be queried in the coordinates it is placed in
+        let terrain_path = &description.meshes.iter().find(|mesh| mesh.name == "terrain").expect("Scene has no terrain").path;
+        let terrain = mesh::Terrain::from_model(models.remove(terrain_path).unwrap()).unwrap_or_else(|e| panic!("{}", e));
+
+        // The meshes picking tests against, by the name they are declared with in the scene file. Picking only needs
+        // their triangles, so these point into the loaded models rather than being copies. The terrain has its own
+        let pickable_meshes: HashMap<&str, &mesh::Mesh> = description.meshes.iter().filter_map(|mesh_description| {
+            let mesh = if mesh_description.path == *terrain_path {
+                Some(&*terrain)
+            } else {
+                models.get(&mesh_description.path)?.part_or_single(mesh_description.object.as_deref()).ok()
+            };
+            mesh.map(|mesh| (mesh_description.name.as_str(), mesh))
+        }).collect();
+
+        let root_node = loaded_scene.root;
+        let terrain_node = scene.find_path("root/terrain").expect("Scene has no terrain node");
+        let heli_body_node = scene.find_path("root/terrain/heli_body").expect("Scene has no helicopter");
 
         // Adding shaders        
-        let shader = unsafe {
-            shader::ShaderBuilder::new()
-                .attach_file("./shaders/simple.vert")
-                .attach_file("./shaders/simple.frag")
-                .link()
-        };
-
+        // With SHOW_NORMALS defined, the fragment shader shows the normals (Task 1c) instead of lighting (Task 1d).
+        // N switches between them
+        let mut shaders = ShaderVariants::new(&["./shaders/simple.vert", "./shaders/simple.frag"]);
+        let mut shader_defines = Defines::new();
+        let mut normals_key_was_down = false;
         unsafe {
-            gl::UseProgram(shader.program_id);
+            gl::UseProgram(shaders.get(&shader_defines).unwrap_or_else(|e| panic!("{}", e)).program_id);
         }
 
         // Used to demonstrate keyboard handling -- feel free to remove
-        let mut _arbitrary_number = 0.0;
+        let _arbitrary_number = 0.0;
 
         let first_frame_time = std::time::Instant::now();
         let mut last_frame_time = first_frame_time;
-
-        // Initialize vector containing position and orientation of camera. Notation eta borrowed from TTK4190
-        // values are trans(x), trans(y), trans(z), rot(y), rot(x)
-        let mut eta: Vec<f32> = vec![
-            0.0, 0.0, -2.0, 0.0, 0.0
-        ];
+        let mut last_cull_counts = (0, 0);
+        let mut left_button_was_down = false;
+        // A grabbed cursor is hidden and kept in the window, so the mouse can turn the camera freely. G toggles it
+        let mut cursor_grabbed = false;
+        let mut grab_key_was_down = false;
+
+        // A free camera steered from the keyboard, and one chasing the helicopter. C switches between them
+        let aspect_ratio = SCREEN_W as f32 / SCREEN_H as f32;
+        let mut free_camera = Camera::new(glm::vec3(0.0, 0.0, 2.0), aspect_ratio);
+        let mut chase_camera = Camera::new(glm::zero(), aspect_ratio);
+        chase_camera.chase(&scene, ChaseSettings::new(heli_body_node));
+        let mut chasing = false;
+        let mut camera_key_was_down = false;
 
         // The main rendering loop
         loop {
@@ -265,37 +308,56 @@ fn main() {
             last_frame_time = now;
 
             // Handle keyboard input
+            let mut camera_input = CameraInput::none();
             if let Ok(keys) = pressed_keys.lock() {
                 for key in keys.iter() {
                     match key {
                         VirtualKeyCode::A => {
-                            eta[0] += 20.0 * delta_time;
+                            camera_input.movement.x -= CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::D => {
-                            eta[0] -= 20.0 * delta_time;
+                            camera_input.movement.x += CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::W => {
-                            eta[2] += 20.0 * delta_time;
+                            camera_input.movement.z += CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::S => {
-                            eta[2] -= 20.0 * delta_time;
+                            camera_input.movement.z -= CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::Space => {
-                            eta[1] -= 20.0 * delta_time;
+                            camera_input.movement.y += CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::LShift => {
-                            eta[1] += 20.0 * delta_time;
+                            camera_input.movement.y -= CAMERA_SPEED * delta_time;
                         },
                         VirtualKeyCode::Up => {
-                            eta[3] -= delta_time;
+                            camera_input.rotation.y += CAMERA_TURN_SPEED * delta_time;
                         },
                         VirtualKeyCode::Down => {
-                            eta[3] += delta_time;
-                        },VirtualKeyCode::Left => {
-                            eta[4] -= delta_time;
+                            camera_input.rotation.y -= CAMERA_TURN_SPEED * delta_time;
+                        },
+                        VirtualKeyCode::Left => {
+                            camera_input.rotation.x += CAMERA_TURN_SPEED * delta_time;
                         },
                         VirtualKeyCode::Right => {
-                            eta[4] += delta_time;
+                            camera_input.rotation.x -= CAMERA_TURN_SPEED * delta_time;
+                        },
+                        // Free camera modes: fly freely, circle the middle of the terrain, or circle the helicopter
+                        VirtualKeyCode::Key1 => {
+                            free_camera.fly();
+                            chasing = false;
+                        },
+                        VirtualKeyCode::Key2 => {
+                            if !matches!(free_camera.mode, CameraMode::Orbit { .. }) {
+                                free_camera.orbit(scene[terrain_node].subtree_bounds().aabb.center());
+                            }
+                            chasing = false;
+                        },
+                        VirtualKeyCode::Key3 => {
+                            if !matches!(free_camera.mode, CameraMode::Follow { .. }) {
+                                free_camera.follow(&scene, heli_body_node);
+                            }
+                            chasing = false;
                         },
 
                         _ => { }
@@ -303,35 +365,108 @@ fn main() {
                 }
             }
 
+            // Handle mouse input. Moving the mouse looks around while the cursor is grabbed or the right button is held
+            let mut click_position = None;
+            if let Ok(mut mouse) = mouse_state.lock() {
+                let motion = mouse.take_motion();
+                if cursor_grabbed || mouse.is_pressed(MouseButton::Right) {
+                    camera_input.rotation -= motion.delta * MOUSE_SENSITIVITY;
+                }
+                camera_input.zoom += motion.scroll * ZOOM_PER_LINE;
+                let left_button_down = mouse.is_pressed(MouseButton::Left);
+                if left_button_down && !left_button_was_down {
+                    click_position = Some(mouse.position);
+                }
+                left_button_was_down = left_button_down;
+            }
+
+            let grab_key_down = pressed_keys.lock().is_ok_and(|keys| keys.contains(&VirtualKeyCode::G));
+            if grab_key_down && !grab_key_was_down {
+                cursor_grabbed = !cursor_grabbed;
+                if let Err(e) = context.window().set_cursor_grab(cursor_grabbed) {
+                    println!("Could not grab the cursor: {}", e);
+                    cursor_grabbed = false;
+                }
+                context.window().set_cursor_visible(!cursor_grabbed);
+            }
+            grab_key_was_down = grab_key_down;
+
             unsafe {
                 gl::ClearColor(0.163, 0.163, 0.163, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-                // Task 4 uniform matrix to be passed to shader
-                let translate: glm::Mat4 = glm::mat4(
-                    1.0, 0.0, 0.0, eta[0], 
-                    0.0, 1.0, 0.0, eta[1], 
-                    0.0, 0.0, 1.0, eta[2], 
-                    0.0, 0.0, 0.0, 1.0,
-                );
-                let rotate_x: glm::Mat4 = glm::mat4(
-                    1.0, 0.0, 0.0, 0.0, 
-                    0.0, eta[3].cos(), -eta[3].sin(), 0.0, 
-                    0.0, eta[3].sin(), eta[3].cos(), 0.0, 
-                    0.0, 0.0, 0.0, 1.0,
-                );
-                let rotate_y: glm::Mat4 = glm::mat4(
-                    eta[4].cos(), 0.0, eta[4].sin(), 0.0, 
-                    0.0, 1.0, 0.0, 0.0, 
-                    -eta[4].sin(), 0.0, eta[4].cos(), 0.0, 
-                    0.0, 0.0, 0.0, 1.0,
-                );
-                let perspective_transform: glm::Mat4 = glm::perspective(1.0, 1.0, 1.0, 1000.0);
-
-                let transform_matrix: glm::Mat4 = perspective_transform * rotate_x * rotate_y * translate;
+                // Pick up edits to the shader files. An edit that does not compile leaves the last program in use
+                match shaders.reload_if_changed() {
+                    Ok(true) => println!("Reloaded shaders"),
+                    Ok(false) => {},
+                    Err(e) => println!("{}", e),
+                }
+                let normals_key_down = pressed_keys.lock().is_ok_and(|keys| keys.contains(&VirtualKeyCode::N));
+                if normals_key_down && !normals_key_was_down {
+                    let mut toggled = shader_defines.clone();
+                    if toggled.remove("SHOW_NORMALS").is_none() {
+                        toggled.insert("SHOW_NORMALS".to_string(), String::new());
+                    }
+                    // A variant that does not build leaves the current one in use
+                    match shaders.get(&toggled) {
+                        Ok(_) => shader_defines = toggled,
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                normals_key_was_down = normals_key_down;
+                // Reloading and switching variants both change the program, so it is bound again every frame
+                if let Ok(shader) = shaders.get(&shader_defines) {
+                    gl::UseProgram(shader.program_id);
+                }
+
+                // Fly the helicopter along its path. Yaw, then pitch, then roll, to keep the angles independent
+                let heading = toolbox::simple_heading_animation(elapsed);
+                let ground_height = terrain.height_at(heading.x, heading.z).unwrap_or(0.0);
+                scene[heli_body_node].set_position(glm::vec3(heading.x, ground_height + HELI_ALTITUDE, heading.z));
+                scene[heli_body_node].set_rotation_euler(glm::vec3(heading.pitch, heading.yaw, heading.roll), EulerOrder::YXZ);
 
                 // Issue the necessary commands to draw your scene here
-                draw_scene(&root_node, &transform_matrix)
+                update_node_transformations(&mut scene, root_node, &glm::identity());
+                let camera_key_down = pressed_keys.lock().is_ok_and(|keys| keys.contains(&VirtualKeyCode::C));
+                if camera_key_down && !camera_key_was_down {
+                    chasing = !chasing;
+                }
+                camera_key_was_down = camera_key_down;
+                // Both cameras keep up with the scene, but only the one in use is steered
+                let idle_input = CameraInput::none();
+                free_camera.update(if chasing { &idle_input } else { &camera_input }, &scene, delta_time);
+                chase_camera.update(&idle_input, &scene, delta_time);
+                let camera = if chasing { &chase_camera } else { &free_camera };
+                let view_matrix = camera.view_matrix();
+                let perspective_transform = camera.projection_matrix();
+                let transform_matrix = camera.view_projection_matrix();
+                gl::Uniform3fv(8, 1, camera.position.as_ptr());
+
+                // Report what was clicked. A grabbed cursor stays put wherever it was, so the middle of the screen is used
+                if let Some(cursor_position) = click_position {
+                    let window_size = context.window().inner_size();
+                    let screen_size = glm::vec2(window_size.width as f32, window_size.height as f32);
+                    let pixel = if cursor_grabbed { screen_size / 2.0 } else { cursor_position };
+                    let ray = Ray::from_screen(pixel, screen_size, &view_matrix, &perspective_transform);
+                    let mesh_of = |node| loaded_scene.mesh_of(node).and_then(|name| pickable_meshes.get(name).copied());
+                    match picking::pick(&scene, root_node, &ray, mesh_of) {
+                        Some(hit) => println!(
+                            "Picked {} at distance {:.2}, point [{:.2}, {:.2}, {:.2}]",
+                            scene[hit.node].name, hit.distance, hit.point.x, hit.point.y, hit.point.z
+                        ),
+                        None => println!("Picked nothing"),
+                    }
+                }
+
+                let cull_result = frustum::cull_scene(&scene, root_node, &Frustum::from_matrix(&transform_matrix));
+                draw_scene(&scene, &cull_result.visible, &transform_matrix);
+
+                // Only touch the window title when the counts change, setting it can be slow
+                let cull_counts = (cull_result.drawn(), cull_result.culled);
+                if cull_counts != last_cull_counts {
+                    context.window().set_title(&format!("Gloom-rs - {} drawn, {} culled", cull_counts.0, cull_counts.1));
+                    last_cull_counts = cull_counts;
+                }
             }
 
             context.swap_buffers().unwrap();
@@ -342,7 +477,7 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -356,7 +491,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -365,6 +500,27 @@ fn main() {
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
                 *control_flow = ControlFlow::Exit;
             },
+            // Pass the mouse on to the rendering thread as well. Raw device motion keeps coming when the cursor is grabbed
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.cursor_moved(position.x, position.y);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button, .. }, .. } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.button(button, state);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.scrolled(delta);
+                }
+            },
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                if let Ok(mut mouse) = arc_mouse_state.lock() {
+                    mouse.moved(delta.0, delta.1);
+                }
+            },
             // Keep track of currently pressed keys to send to the rendering thread
             Event::WindowEvent { event: WindowEvent::KeyboardInput {
                 input: KeyboardInput { state: key_state, virtual_keycode: Some(keycode), .. }, .. }, .. } => {
@@ -386,11 +542,8 @@ fn main() {
                 }
 
                 // Handle escape separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
-                    },
-                    _ => { }
+                if keycode == Escape {
+                    *control_flow = ControlFlow::Exit;
                 }
             },
             _ => { }
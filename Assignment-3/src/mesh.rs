@@ -1,92 +1,805 @@
-use tobj;
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, Index};
+use std::path::Path;
+
+use crate::bounds::Bounds;
+
+mod primitives;
+pub mod terrain;
 
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
+// Faces meeting at a sharper angle than this keep a hard edge when normals are generated for a mesh without them
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+// Surface properties from an MTL file, passed to the shaders as uniforms
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub ambient: glm::Vec3,
+    pub diffuse: glm::Vec3,
+    pub specular: glm::Vec3,
+    pub shininess: f32,
+    pub dissolve: f32,
+    // Texture paths, relative to the working directory
+    pub diffuse_texture: Option<String>,
+    pub normal_texture: Option<String>,
+}
+
+impl Default for Material {
+    // Leaves the color of the surface to the vertex colors, lit by diffuse light only
+    fn default() -> Material {
+        Material {
+            name: String::new(),
+            ambient: glm::zero(),
+            diffuse: glm::vec3(1.0, 1.0, 1.0),
+            specular: glm::zero(),
+            shininess: 1.0,
+            dissolve: 1.0,
+            diffuse_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+impl Material {
+    // Texture file names in MTL files are relative to the directory of the OBJ file
+    pub fn from_mtl(material: &tobj::Material, directory: &Path) -> Material {
+        let texture_path = |name: &str| {
+            if name.is_empty() { None } else { Some(directory.join(name).to_string_lossy().into_owned()) }
+        };
+        Material {
+            name: material.name.clone(),
+            ambient: glm::make_vec3(&material.ambient),
+            diffuse: glm::make_vec3(&material.diffuse),
+            specular: glm::make_vec3(&material.specular),
+            shininess: material.shininess,
+            dissolve: material.dissolve,
+            diffuse_texture: texture_path(&material.diffuse_texture),
+            normal_texture: texture_path(&material.normal_texture),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Mesh {
+    pub name: String,
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
+    // Two per vertex, or empty if the OBJ file had no vt lines for this mesh
+    pub texcoords: Vec<f32>,
+    // Four per vertex, the tangent along which u grows and the handedness of the tangent frame.
+    // The bitangent is tangent.w * cross(normal, tangent.xyz)
+    pub tangents: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
+    // Extents of the vertices, in the coordinates of the mesh
+    pub bounds: Bounds,
+    // None if the OBJ file had no material for this mesh, in which case it is colored by its vertex colors alone
+    pub material: Option<Material>,
 }
 
 impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
-        Mesh {
+        let mut result = Mesh {
+            name: String::new(),
             vertices: mesh.positions,
             normals: mesh.normals,
+            texcoords: mesh.texcoords,
+            tangents: vec![],
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
             index_count,
+            bounds: Bounds::empty(),
+            material: None,
+        };
+        result.compute_bounds();
+        // OBJ files without vn lines would otherwise leave us with nothing to light the mesh by
+        if result.normals.len() != result.vertices.len() {
+            result.generate_smooth_normals(DEFAULT_CREASE_ANGLE);
+        } else {
+            result.generate_tangents();
+        }
+        result
+    }
+
+    // Has to be called again whenever the vertices move
+    pub fn compute_bounds(&mut self) {
+        let points: Vec<glm::Vec3> = (0..self.vertices.len() / 3).map(|v| self.position(v)).collect();
+        self.bounds = Bounds::from_points(&points);
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.colors = generate_color_vec(color, self.vertices.len() / 3);
+    }
+
+    // Colors the mesh by its material, or by the given color if it has none
+    pub fn set_fallback_color(&mut self, color: [f32; 4]) {
+        if self.material.is_none() {
+            self.set_color(color);
+        }
+    }
+
+    // The material to draw the mesh with, which is the default material if it has none of its own
+    pub fn material_or_default(&self) -> Material {
+        self.material.clone().unwrap_or_default()
+    }
+
+    // Gives every triangle its own three vertices, all with the normal of the triangle
+    #[allow(dead_code)]
+    pub fn generate_flat_normals(&mut self) {
+        let corners: Vec<usize> = self.indices.iter().map(|&i| i as usize).collect();
+        let mut normals = Vec::with_capacity(corners.len() * 3);
+        for triangle in 0..corners.len() / 3 {
+            let (normal, _) = self.triangle_normal_and_angles(triangle);
+            for _ in 0..3 {
+                normals.extend_from_slice(normal.as_slice());
+            }
+        }
+        self.remap_vertices(&corners);
+        self.indices = (0..corners.len() as u32).collect();
+        self.normals = normals;
+        self.generate_tangents();
+    }
+
+    // Averages the normals of the triangles around each vertex, weighted by the angle of the triangle at the vertex.
+    // Triangles whose normals differ by more than crease_angle are not averaged together, which splits the vertices
+    // along the crease. Vertices sharing a position are smoothed together even if the mesh keeps them apart.
+    pub fn generate_smooth_normals(&mut self, crease_angle: f32) {
+        let triangles: Vec<(glm::Vec3, [f32; 3])> = (0..self.indices.len() / 3)
+            .map(|triangle| self.triangle_normal_and_angles(triangle))
+            .collect();
+
+        let mut corners_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, &vertex) in self.indices.iter().enumerate() {
+            corners_at_position.entry(self.position_key(vertex as usize)).or_default().push(corner);
+        }
+
+        let min_cos = crease_angle.cos();
+        let mut sources = vec![];
+        let mut normals = vec![];
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut new_vertices: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        for corner in 0..self.indices.len() {
+            let vertex = self.indices[corner];
+            let face_normal = triangles[corner / 3].0;
+            let mut sum: glm::Vec3 = glm::zero();
+            for &other in &corners_at_position[&self.position_key(vertex as usize)] {
+                let (other_normal, angles) = triangles[other / 3];
+                if glm::dot(&face_normal, &other_normal) >= min_cos {
+                    sum += other_normal * angles[other % 3];
+                }
+            }
+            let normal = if glm::length(&sum) > 1e-12 { glm::normalize(&sum) } else { glm::vec3(0.0, 1.0, 0.0) };
+
+            // Corners of a vertex that ended up with the same normal keep sharing it
+            let key = (vertex, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+            let index = *new_vertices.entry(key).or_insert_with(|| {
+                sources.push(vertex as usize);
+                normals.extend_from_slice(normal.as_slice());
+                (sources.len() - 1) as u32
+            });
+            indices.push(index);
+        }
+        self.remap_vertices(&sources);
+        self.indices = indices;
+        self.normals = normals;
+        self.generate_tangents();
+    }
+
+    // Averages the texture space directions of the triangles around each vertex, weighted by the angle of the triangle
+    // at the vertex, the way MikkTSpace does. Each direction is first projected into the plane of the vertex normal and
+    // normalized, so that neither big nor small UV islands outweigh the others. The handedness goes in w, so mirrored
+    // texture coordinates get a bitangent pointing the other way. A vertex shared by triangles on both sides of a
+    // mirror seam is split in two first, as a single tangent frame cannot be right for both of them.
+    pub fn generate_tangents(&mut self) {
+        let mut directions = vec![];
+        if self.texcoords.len() / 2 == self.vertices.len() / 3 {
+            directions = (0..self.indices.len() / 3).map(|triangle| self.texture_directions(triangle)).collect();
+            self.split_mirrored_vertices(&directions);
+        }
+
+        let vertex_count = self.vertices.len() / 3;
+        let mut tangent_sums: Vec<glm::Vec3> = vec![glm::zero(); vertex_count];
+        let mut bitangent_sums: Vec<glm::Vec3> = vec![glm::zero(); vertex_count];
+        for (triangle, direction) in directions.iter().enumerate() {
+            if let Some((tangent, bitangent, _)) = direction {
+                let (_, angles) = self.triangle_normal_and_angles(triangle);
+                for (k, &vertex) in self.indices[triangle * 3..triangle * 3 + 3].iter().enumerate() {
+                    let normal = glm::make_vec3(&self.normals[vertex as usize * 3..vertex as usize * 3 + 3]);
+                    tangent_sums[vertex as usize] += unit_in_plane(tangent, &normal) * angles[k];
+                    bitangent_sums[vertex as usize] += unit_in_plane(bitangent, &normal) * angles[k];
+                }
+            }
+        }
+
+        let mut tangents = Vec::with_capacity(vertex_count * 4);
+        for vertex in 0..vertex_count {
+            let normal = glm::make_vec3(&self.normals[vertex * 3..vertex * 3 + 3]);
+            // Already in the plane, but normalized again now that the directions are added up
+            let mut tangent = unit_in_plane(&tangent_sums[vertex], &normal);
+            if glm::length(&tangent) < 1e-12 {
+                tangent = any_orthogonal(&normal);
+            }
+            let tangent = glm::normalize(&tangent);
+            let handedness = if glm::dot(&glm::cross(&normal, &tangent), &bitangent_sums[vertex]) < 0.0 { -1.0 } else { 1.0 };
+            tangents.extend_from_slice(&[tangent.x, tangent.y, tangent.z, handedness]);
         }
+        self.tangents = tangents;
+    }
+
+    // Texture coordinates projected straight down onto the xz plane, spanning 0 to 1 over the extent of the mesh.
+    // Good enough for terrain, which is mostly flat and rarely comes with texture coordinates of its own
+    pub fn generate_planar_texcoords(&mut self) {
+        let positions: Vec<glm::Vec3> = (0..self.vertices.len() / 3).map(|v| self.position(v)).collect();
+        let min = positions.iter().fold(glm::vec3(f32::MAX, f32::MAX, f32::MAX), |a, p| glm::min2(&a, p));
+        let max = positions.iter().fold(glm::vec3(f32::MIN, f32::MIN, f32::MIN), |a, p| glm::max2(&a, p));
+        let size = glm::max2(&(max - min), &glm::vec3(1e-6, 1e-6, 1e-6));
+        self.texcoords = positions.iter()
+            .flat_map(|p| vec![(p.x - min.x) / size.x, (p.z - min.z) / size.z])
+            .collect();
+        self.generate_tangents();
+    }
+
+    // Tangent space bitangent of a vertex, as a shader would reconstruct it from the normal and tangent
+    #[cfg(test)]
+    pub fn bitangent(&self, vertex: usize) -> glm::Vec3 {
+        let normal = glm::make_vec3(&self.normals[vertex * 3..vertex * 3 + 3]);
+        let tangent = glm::make_vec3(&self.tangents[vertex * 4..vertex * 4 + 3]);
+        glm::cross(&normal, &tangent) * self.tangents[vertex * 4 + 3]
+    }
+
+    fn position(&self, vertex: usize) -> glm::Vec3 {
+        glm::make_vec3(&self.vertices[vertex * 3..vertex * 3 + 3])
+    }
+
+    fn texcoord(&self, vertex: usize) -> glm::Vec2 {
+        glm::vec2(self.texcoords[vertex * 2], self.texcoords[vertex * 2 + 1])
+    }
+
+    fn position_key(&self, vertex: usize) -> [u32; 3] {
+        let p = self.position(vertex);
+        [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+    }
+
+    // The directions in which u and v grow across a triangle, and whether its texture coordinates are mirrored, that
+    // is wound the other way around than its positions. None if the texture coordinates are degenerate
+    fn texture_directions(&self, triangle: usize) -> Option<(glm::Vec3, glm::Vec3, bool)> {
+        let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+        let p: Vec<glm::Vec3> = corners.iter().map(|&v| self.position(v as usize)).collect();
+        let uv: Vec<glm::Vec2> = corners.iter().map(|&v| self.texcoord(v as usize)).collect();
+        let (edge1, edge2) = (p[1] - p[0], p[2] - p[0]);
+        let (duv1, duv2) = (uv[1] - uv[0], uv[2] - uv[0]);
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        Some((tangent, bitangent, determinant < 0.0))
+    }
+
+    // Gives the mirrored triangles around a vertex a copy of it, if the vertex is also used by unmirrored ones
+    fn split_mirrored_vertices(&mut self, directions: &[Option<(glm::Vec3, glm::Vec3, bool)>]) {
+        let vertex_count = self.vertices.len() / 3;
+        let mut sources: Vec<usize> = (0..vertex_count).collect();
+        let mut first_mirrored: Vec<Option<bool>> = vec![None; vertex_count];
+        let mut copies: HashMap<u32, u32> = HashMap::new();
+        for (corner, index) in self.indices.iter_mut().enumerate() {
+            let mirrored = match directions[corner / 3] {
+                Some((_, _, mirrored)) => mirrored,
+                None => continue,
+            };
+            let vertex = *index as usize;
+            match first_mirrored[vertex] {
+                None => first_mirrored[vertex] = Some(mirrored),
+                Some(first) if first != mirrored => {
+                    *index = *copies.entry(*index).or_insert_with(|| {
+                        sources.push(vertex);
+                        (sources.len() - 1) as u32
+                    });
+                },
+                Some(_) => {},
+            }
+        }
+        if sources.len() > vertex_count {
+            self.remap_vertices(&sources);
+        }
+    }
+
+    // Unit normal of a triangle, and its interior angle at each of its corners. Degenerate triangles get a zero normal
+    fn triangle_normal_and_angles(&self, triangle: usize) -> (glm::Vec3, [f32; 3]) {
+        let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+        let p = [
+            self.position(corners[0] as usize),
+            self.position(corners[1] as usize),
+            self.position(corners[2] as usize),
+        ];
+        let cross = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+        if glm::length(&cross) < 1e-12 {
+            return (glm::zero(), [0.0; 3]);
+        }
+        let mut angles = [0.0; 3];
+        for (k, angle) in angles.iter_mut().enumerate() {
+            let a = glm::normalize(&(p[(k + 1) % 3] - p[k]));
+            let b = glm::normalize(&(p[(k + 2) % 3] - p[k]));
+            *angle = glm::dot(&a, &b).clamp(-1.0, 1.0).acos();
+        }
+        (glm::normalize(&cross), angles)
+    }
+
+    // Rebuilds the per-vertex attributes so that new vertex i is a copy of old vertex sources[i]
+    fn remap_vertices(&mut self, sources: &[usize]) {
+        fn remap(values: &[f32], width: usize, sources: &[usize]) -> Vec<f32> {
+            if values.is_empty() {
+                return vec![];
+            }
+            sources.iter().flat_map(|&i| values[i * width..i * width + width].iter().cloned()).collect()
+        }
+        self.vertices = remap(&self.vertices, 3, sources);
+        self.normals = remap(&self.normals, 3, sources);
+        self.texcoords = remap(&self.texcoords, 2, sources);
+        self.tangents = remap(&self.tangents, 4, sources);
+        self.colors = remap(&self.colors, 4, sources);
     }
 }
 
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
+// The part of v orthogonal to the unit vector normal, normalized. Zero if v is parallel to normal
+fn unit_in_plane(v: &glm::Vec3, normal: &glm::Vec3) -> glm::Vec3 {
+    let projected = v - normal * glm::dot(normal, v);
+    if glm::length(&projected) < 1e-12 { glm::zero() } else { glm::normalize(&projected) }
+}
+
+// Some unit vector orthogonal to the given unit vector
+fn any_orthogonal(v: &glm::Vec3) -> glm::Vec3 {
+    let axis = if v.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+    glm::normalize(&glm::cross(v, &axis))
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    Load { path: String, error: tobj::LoadError },
+    MissingPart { model: String, part: String },
+    PartCount { model: String, expected: usize, found: usize },
+    Heightmap { path: String, error: image::ImageError },
+    HeightmapTooSmall { name: String, width: u32, height: u32 },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Load { path, error } => write!(f, "Failed to load model {}: {}", path, error),
+            ModelError::MissingPart { model, part } => write!(f, "Model {} has no part named {}", model, part),
+            ModelError::PartCount { model, expected, found } =>
+                write!(f, "Model {} should have {} part(s), but has {}", model, expected, found),
+            ModelError::Heightmap { path, error } => write!(f, "Failed to load heightmap {}: {}", path, error),
+            ModelError::HeightmapTooSmall { name, width, height } =>
+                write!(f, "Heightmap {} is {}x{} pixels, but needs at least 2x2", name, width, height),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+// Every object in an OBJ file, each loaded as a separate Mesh named after the object
+#[derive(Clone)]
+pub struct Model {
+    pub name: String,
+    pub parts: Vec<Mesh>,
+}
+
+impl Model {
+    pub fn load(path: &str) -> Result<Model, ModelError> {
+        println!("Loading model {}...", path);
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load terrain model");
+        let (models, materials) = tobj::load_obj(path, true)
+            .map_err(|error| ModelError::Load { path: path.to_string(), error })?;
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-        if models.len() > 1 || models.len() == 0 { panic!("Please use a model with a single mesh") }
 
-        let terrain = models[0].to_owned();
-        println!("Loaded {} with {} points and {} triangles.", terrain.name, terrain.mesh.positions.len() /3, terrain.mesh.indices.len() / 3);
+        for model in &models {
+            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+        }
+
+        Ok(Model::from_obj_models(path, models, &materials))
+    }
+
+    // Meshes with a material are given white vertex colors, so that the material alone decides their color
+    pub fn from_obj_models(path: &str, models: Vec<tobj::Model>, materials: &[tobj::Material]) -> Model {
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Model {
+            name: path.to_string(),
+            parts: models.into_iter().map(|model| {
+                let material = model.mesh.material_id
+                    .and_then(|id| materials.get(id))
+                    .map(|material| Material::from_mtl(material, directory));
+                let alpha = material.as_ref().map_or(1.0, |material| material.dissolve);
+                let mut mesh = Mesh::from(model.mesh, [1.0, 1.0, 1.0, alpha]);
+                mesh.name = model.name;
+                mesh.material = material;
+                mesh
+            }).collect(),
+        }
+    }
+
+    pub fn part(&self, name: &str) -> Result<&Mesh, ModelError> {
+        self.parts.iter().find(|part| part.name == name)
+            .ok_or_else(|| self.missing_part(name))
+    }
+
+    // The only part of a model that is expected to consist of a single object
+    pub fn single_part(&self) -> Result<&Mesh, ModelError> {
+        match self.parts.len() {
+            1 => Ok(&self.parts[0]),
+            found => Err(ModelError::PartCount { model: self.name.clone(), expected: 1, found }),
+        }
+    }
+
+    // The named part, or the only part if no name is given
+    pub fn part_or_single(&self, name: Option<&str>) -> Result<&Mesh, ModelError> {
+        match name {
+            Some(name) => self.part(name),
+            None => self.single_part(),
+        }
+    }
+
+    fn missing_part(&self, name: &str) -> ModelError {
+        ModelError::MissingPart { model: self.name.clone(), part: name.to_string() }
+    }
+}
+
+// A model consisting of a single mesh. Dereferences to that mesh. Built from a heightmap by `terrain::TerrainBuilder`
+pub struct Terrain {
+    pub model: Model,
+    // For height queries, built from the mesh as it was when the terrain was made
+    surface: terrain::SurfaceGrid,
+}
+
+impl Terrain {
+    #[allow(dead_code)]
+    pub fn load(path: &str) -> Result<Terrain, ModelError> {
+        Terrain::from_model(Model::load(path)?)
+    }
+
+    pub fn from_model(mut model: Model) -> Result<Terrain, ModelError> {
+        model.single_part()?;
+        // So that the terrain can always be given a diffuse texture
+        if model.parts[0].texcoords.is_empty() {
+            model.parts[0].generate_planar_texcoords();
+        }
+        Ok(Terrain::new(model))
+    }
+}
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+impl Deref for Terrain {
+    type Target = Mesh;
+    fn deref(&self) -> &Mesh {
+        &self.model.parts[0]
     }
 }
 
-use std::ops::Index;
+// The parts of the helicopter model, in the order they can be indexed in
+const HELICOPTER_PARTS: [(&str, [f32; 4]); 4] = [
+    ("Body_body",             [0.3, 0.3, 0.3, 1.0]),
+    ("Main_Rotor_main_rotor", [0.3, 0.1, 0.1, 1.0]),
+    ("Tail_Rotor_tail_rotor", [0.1, 0.3, 0.1, 1.0]),
+    ("Door_door",             [0.1, 0.1, 0.3, 1.0]),
+];
+
 pub struct Helicopter {
-    pub body: Mesh,
-    pub main_rotor: Mesh,
-    pub tail_rotor: Mesh,
-    pub door: Mesh,
+    pub model: Model,
+    parts: [usize; 4],
 }
 
 // You can use square brackets to access the components of the helicopter, if you want to use loops!
 impl Index<usize> for Helicopter {
     type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
+    fn index(&self, i: usize) -> &Mesh {
         match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
+            0..=3 => &self.model.parts[self.parts[i]],
             _ => panic!("Invalid index, try [0,3]"),
         }
     }
 }
 
 impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+    #[allow(dead_code)]
+    pub fn load(path: &str) -> Result<Helicopter, ModelError> {
+        Helicopter::from_model(Model::load(path)?)
+    }
 
-        for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+    #[allow(dead_code)]
+    pub fn from_model(mut model: Model) -> Result<Helicopter, ModelError> {
+        let mut parts = [0; 4];
+        for (i, &(name, color)) in HELICOPTER_PARTS.iter().enumerate() {
+            parts[i] = model.parts.iter().position(|part| part.name == name)
+                .ok_or_else(|| model.missing_part(name))?;
+            model.parts[parts[i]].set_fallback_color(color);
+        }
+        Ok(Helicopter { model, parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn load_obj_str(source: &str) -> Model {
+        let (models, materials) = tobj::load_obj_buf(&mut source.as_bytes(), true, |_| {
+            tobj::load_mtl_buf(&mut MATERIALS.as_bytes())
+        }).unwrap();
+        Model::from_obj_models("models/test.obj", models, &materials)
+    }
+
+    const MATERIALS: &str = "
+newmtl shiny
+Ka 0.1 0.1 0.1
+Kd 0.8 0.2 0.2
+Ks 1.0 1.0 1.0
+Ns 64
+d 0.5
+map_Kd textures/shiny.png
+";
+
+    const TWO_TRIANGLES: &str = "
+o first
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+o second
+v 0 0 1
+v 1 0 1
+v 0 1 1
+f 4 5 6
+";
+
+    #[test]
+    fn every_object_becomes_a_named_part() {
+        let model = load_obj_str(TWO_TRIANGLES);
+        assert_eq!(model.parts.len(), 2);
+        assert_eq!(model.part("second").unwrap().vertices[2], 1.0);
+        assert_eq!(model.part("second").unwrap().index_count, 3);
+        match model.part("third") {
+            Err(ModelError::MissingPart { part, .. }) => assert_eq!(part, "third"),
+            _ => panic!("Expected missing part error"),
+        }
+    }
+
+    #[test]
+    fn materials_are_taken_from_mtl_files() {
+        let model = load_obj_str(&format!("mtllib test.mtl\nusemtl shiny{}", TWO_TRIANGLES));
+        let material = model.part("first").unwrap().material.clone().unwrap();
+        assert_eq!(material.diffuse, glm::vec3(0.8, 0.2, 0.2));
+        assert_eq!(material.specular, glm::vec3(1.0, 1.0, 1.0));
+        assert_eq!(material.shininess, 64.0);
+        let expected_texture = Path::new("models").join("textures/shiny.png");
+        assert_eq!(material.diffuse_texture.as_deref(), expected_texture.to_str());
+        // Vertex colors no longer tint the material, apart from its transparency
+        assert_eq!(model.part("first").unwrap().colors[..4], [1.0, 1.0, 1.0, 0.5]);
+        assert_eq!(model.part("second").unwrap().material, Some(material));
+    }
+
+    #[test]
+    fn hard_coded_colors_are_only_a_fallback() {
+        let mut model = load_obj_str(TWO_TRIANGLES);
+        model.parts[0].set_fallback_color([0.3, 0.3, 0.3, 1.0]);
+        assert_eq!(model.parts[0].colors[..4], [0.3, 0.3, 0.3, 1.0]);
+        assert_eq!(model.parts[0].material_or_default(), Material::default());
+
+        let mut model = load_obj_str(&format!("mtllib test.mtl\nusemtl shiny{}", TWO_TRIANGLES));
+        model.parts[0].set_fallback_color([0.3, 0.3, 0.3, 1.0]);
+        assert_eq!(model.parts[0].colors[..4], [1.0, 1.0, 1.0, 0.5]);
+    }
+
+    // A tent: two triangles meeting at a 90 degree ridge along the z axis
+    pub(super) const TENT: &str = "
+v -1 0 0
+v 0 1 0
+v 0 1 1
+v -1 0 1
+v 1 0 0
+v 1 0 1
+f 1 3 2
+f 1 4 3
+f 2 3 6
+f 2 6 5
+";
+
+    fn normal_at(mesh: &Mesh, index: usize) -> glm::Vec3 {
+        let vertex = mesh.indices[index] as usize;
+        glm::make_vec3(&mesh.normals[vertex * 3..vertex * 3 + 3])
+    }
+
+    #[test]
+    fn missing_normals_are_generated_on_load() {
+        let model = load_obj_str(TENT);
+        let mesh = &model.parts[0];
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
+        assert_eq!(mesh.colors.len() / 4, mesh.vertices.len() / 3);
+        // The ridge is sharper than the default crease angle, so each side stays flat
+        let left = glm::normalize(&glm::vec3(-1.0, 1.0, 0.0));
+        let right = glm::normalize(&glm::vec3(1.0, 1.0, 0.0));
+        for i in 0..6 {
+            assert!(glm::distance(&normal_at(mesh, i), &left) < 1e-5);
+            assert!(glm::distance(&normal_at(mesh, i + 6), &right) < 1e-5);
+        }
+        // Ridge vertices are split in two, one for each side
+        assert_eq!(mesh.vertices.len() / 3, 8);
+    }
+
+    #[test]
+    fn smooth_normals_are_shared_below_the_crease_angle() {
+        let mut mesh = load_obj_str(TENT).parts.remove(0);
+        mesh.generate_smooth_normals(std::f32::consts::PI);
+        // Both sides meet the ridge at the same angle, so the ridge normal points straight up
+        assert!(glm::distance(&normal_at(&mesh, 1), &glm::vec3(0.0, 1.0, 0.0)) < 1e-5);
+        assert!(glm::distance(&normal_at(&mesh, 0), &glm::normalize(&glm::vec3(-1.0, 1.0, 0.0))) < 1e-5);
+    }
+
+    #[test]
+    fn flat_normals_give_every_triangle_its_own_vertices() {
+        let mut mesh = load_obj_str(TENT).parts.remove(0);
+        mesh.generate_flat_normals();
+        assert_eq!(mesh.vertices.len() / 3, 12);
+        assert_eq!(mesh.indices, (0..12).collect::<Vec<u32>>());
+        assert_eq!(mesh.index_count, 12);
+        assert!(glm::distance(&normal_at(&mesh, 11), &glm::normalize(&glm::vec3(1.0, 1.0, 0.0))) < 1e-5);
+    }
+
+    // A unit quad in the xy plane, with texture coordinates that mirror u along x = 1 and a second quad next to it
+    const TEXTURED_QUADS: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 2 0 0
+v 2 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+f 1/1/1 3/3/1 4/4/1
+f 2/2/1 5/1/1 6/4/1
+f 2/2/1 6/4/1 3/3/1
+";
+
+    pub(super) fn assert_orthonormal_frames(mesh: &Mesh) {
+        assert_eq!(mesh.tangents.len() / 4, mesh.vertices.len() / 3);
+        for vertex in 0..mesh.vertices.len() / 3 {
+            let normal = glm::make_vec3(&mesh.normals[vertex * 3..vertex * 3 + 3]);
+            let tangent = glm::make_vec3(&mesh.tangents[vertex * 4..vertex * 4 + 3]);
+            let bitangent = mesh.bitangent(vertex);
+            assert!((glm::length(&tangent) - 1.0).abs() < 1e-5);
+            assert!((glm::length(&bitangent) - 1.0).abs() < 1e-5);
+            assert!(glm::dot(&tangent, &normal).abs() < 1e-5);
+            assert!(glm::dot(&bitangent, &normal).abs() < 1e-5);
+            assert!(glm::dot(&tangent, &bitangent).abs() < 1e-5);
+            assert!(mesh.tangents[vertex * 4 + 3].abs() == 1.0);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_texture_coordinates() {
+        let mesh = load_obj_str(TEXTURED_QUADS).parts.remove(0);
+        assert_eq!(mesh.texcoords.len() / 2, mesh.vertices.len() / 3);
+        assert_orthonormal_frames(&mesh);
+        let first = mesh.indices[0] as usize;
+        assert!(glm::distance(&glm::make_vec3(&mesh.tangents[first * 4..first * 4 + 3]), &glm::vec3(1.0, 0.0, 0.0)) < 1e-5);
+        assert!(glm::distance(&mesh.bitangent(first), &glm::vec3(0.0, 1.0, 0.0)) < 1e-5);
+        // The mirrored quad has u growing along -x, but v still along y, so its frame is left-handed
+        let mirrored = mesh.indices[7] as usize;
+        assert!(glm::distance(&glm::make_vec3(&mesh.tangents[mirrored * 4..mirrored * 4 + 3]), &glm::vec3(-1.0, 0.0, 0.0)) < 1e-5);
+        assert_eq!(mesh.tangents[mirrored * 4 + 3], -1.0);
+        assert!(glm::distance(&mesh.bitangent(mirrored), &glm::vec3(0.0, 1.0, 0.0)) < 1e-5);
+    }
+
+    #[test]
+    fn uv_island_scale_does_not_weigh_on_tangents() {
+        // Two triangles meeting at the origin at right angles, with u along x on one and along y on the other.
+        // The texture is squeezed a hundredfold on the second, so its raw tangent is a hundred times as long
+        let mesh = load_obj_str("
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v -1 0 0
+v 0 -1 0
+vt 0 0
+vt 1 0
+vt 0 1
+vt 0 0.01
+vt -0.01 0
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+f 1/1/1 4/4/1 5/5/1
+").parts.remove(0);
+        assert_orthonormal_frames(&mesh);
+        let shared = mesh.indices[0] as usize;
+        assert_eq!(mesh.indices[3] as usize, shared);
+        let tangent = glm::make_vec3(&mesh.tangents[shared * 4..shared * 4 + 3]);
+        assert!(glm::distance(&tangent, &glm::normalize(&glm::vec3(1.0, 1.0, 0.0))) < 1e-5, "{}", tangent);
+        assert_eq!(mesh.tangents[shared * 4 + 3], 1.0);
+    }
+
+    #[test]
+    fn vertices_on_a_mirror_seam_are_split() {
+        // Both quads share the vertices at x = 1, with the same position, texture coordinates and normal
+        let mesh = load_obj_str(TEXTURED_QUADS).parts.remove(0);
+        assert_eq!(mesh.vertices.len() / 3, 8);
+        assert_eq!(mesh.index_count, 12);
+        assert_orthonormal_frames(&mesh);
+
+        // Every corner gets the frame of its own quad, the seam included
+        for (corner, &vertex) in mesh.indices.iter().enumerate() {
+            let vertex = vertex as usize;
+            let (tangent, handedness) = if corner < 6 { (glm::vec3(1.0, 0.0, 0.0), 1.0) } else { (glm::vec3(-1.0, 0.0, 0.0), -1.0) };
+            assert!(glm::distance(&glm::make_vec3(&mesh.tangents[vertex * 4..vertex * 4 + 3]), &tangent) < 1e-5);
+            assert_eq!(mesh.tangents[vertex * 4 + 3], handedness);
+            assert!(glm::distance(&mesh.bitangent(vertex), &glm::vec3(0.0, 1.0, 0.0)) < 1e-5);
+        }
+        // The split vertices are copies, so the seam stays closed
+        let seam: Vec<glm::Vec3> = mesh.indices[6..].iter()
+            .map(|&v| mesh.position(v as usize))
+            .filter(|p| p.x == 1.0)
+            .collect();
+        assert_eq!(seam.len(), 3);
+        assert_eq!(mesh.bounds, Bounds::from_points(&[glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 1.0, 0.0)]));
+    }
+
+    #[test]
+    fn tangent_frames_are_orthonormal_with_and_without_texture_coordinates() {
+        let mut tent = load_obj_str(TENT).parts.remove(0);
+        assert!(tent.texcoords.is_empty());
+        assert_orthonormal_frames(&tent);
+        tent.generate_smooth_normals(std::f32::consts::PI);
+        assert_orthonormal_frames(&tent);
+
+        let mut quads = load_obj_str(TEXTURED_QUADS).parts.remove(0);
+        quads.generate_flat_normals();
+        assert_eq!(quads.texcoords.len() / 2, 12);
+        assert_orthonormal_frames(&quads);
+    }
+
+    #[test]
+    fn terrain_without_texture_coordinates_gets_planar_ones() {
+        let terrain = Terrain::from_model(load_obj_str(TENT)).unwrap();
+        assert_eq!(terrain.texcoords.len() / 2, terrain.vertices.len() / 3);
+        for vertex in 0..terrain.vertices.len() / 3 {
+            let p = terrain.position(vertex);
+            assert_eq!(terrain.texcoord(vertex), glm::vec2((p.x + 1.0) / 2.0, p.z));
         }
+        assert_orthonormal_frames(&terrain);
+    }
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
+    #[test]
+    fn bounds_are_computed_on_load() {
+        let mesh = &load_obj_str(TENT).parts[0];
+        assert_eq!(mesh.bounds.aabb.min, glm::vec3(-1.0, 0.0, 0.0));
+        assert_eq!(mesh.bounds.aabb.max, glm::vec3(1.0, 1.0, 1.0));
+        for vertex in 0..mesh.vertices.len() / 3 {
+            assert!(mesh.bounds.sphere.contains(&mesh.position(vertex)));
+        }
+    }
 
-        Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
+    #[test]
+    fn views_report_unexpected_models() {
+        match Terrain::from_model(load_obj_str(TWO_TRIANGLES)) {
+            Err(ModelError::PartCount { expected: 1, found: 2, .. }) => {},
+            _ => panic!("Expected part count error"),
+        }
+        match Helicopter::from_model(load_obj_str(TWO_TRIANGLES)) {
+            Err(ModelError::MissingPart { part, .. }) => assert_eq!(part, "Body_body"),
+            _ => panic!("Expected missing part error"),
         }
     }
-}
\ No newline at end of file
+}
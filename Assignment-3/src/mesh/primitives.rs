@@ -0,0 +1,405 @@
+// Procedurally generated meshes, all centered on the origin with y up. They come with normals, texture coordinates,
+// tangents and white vertex colors, and wind their triangles counter-clockwise seen from the outside.
+
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use super::{generate_color_vec, Mesh};
+use crate::bounds::Bounds;
+
+// Fewest segments around an axis that still enclose a volume, and along an axis that still cover some area.
+// The constructors raise smaller counts to these, rather than dividing by zero
+const MIN_SEGMENTS_AROUND: usize = 3;
+const MIN_SEGMENTS_ALONG: usize = 1;
+
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+// A point on the outline that `MeshBuilder::lathe` sweeps around the y axis. The normal is given as (radial, y)
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: glm::Vec2,
+}
+
+impl ProfilePoint {
+    fn new(radius: f32, y: f32, normal: glm::Vec2) -> ProfilePoint {
+        ProfilePoint { radius, y, normal: glm::normalize(&normal) }
+    }
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, position: glm::Vec3, normal: glm::Vec3, texcoord: glm::Vec2) -> u32 {
+        self.vertices.extend_from_slice(position.as_slice());
+        self.normals.extend_from_slice(normal.as_slice());
+        self.texcoords.extend_from_slice(texcoord.as_slice());
+        (self.vertices.len() / 3 - 1) as u32
+    }
+
+    fn position(&self, vertex: u32) -> glm::Vec3 {
+        let i = vertex as usize * 3;
+        glm::make_vec3(&self.vertices[i..i + 3])
+    }
+
+    // Leaves out triangles without area, such as those touching the poles of a sphere
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let (pa, pb, pc) = (self.position(a), self.position(b), self.position(c));
+        if glm::length(&glm::cross(&(pb - pa), &(pc - pa))) > 1e-10 {
+            self.indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    // Corners in counter-clockwise order
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    // A (columns + 1) x (rows + 1) grid of vertices, where f maps texture coordinates to a position and normal.
+    // The derivative of the position along u crossed with the one along v must point the same way as the normal.
+    fn grid<F>(&mut self, columns: usize, rows: usize, f: F)
+        where F: Fn(glm::Vec2) -> (glm::Vec3, glm::Vec3)
+    {
+        let first = (self.vertices.len() / 3) as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let uv = glm::vec2(column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal) = f(uv);
+                self.vertex(position, normal, uv);
+            }
+        }
+        let stride = columns as u32 + 1;
+        for row in 0..rows as u32 {
+            for column in 0..columns as u32 {
+                let corner = first + row * stride + column;
+                self.quad(corner, corner + 1, corner + stride + 1, corner + stride);
+            }
+        }
+    }
+
+    // Sweeps the profile a full turn around the y axis. u goes around the axis and v follows the profile by arc length.
+    // The surface is on the outside when the profile runs upwards along the outer side, or inwards along the top.
+    // Repeating a point with a different normal gives a hard edge.
+    fn lathe(&mut self, profile: &[ProfilePoint], sectors: usize) {
+        let mut lengths = vec![0.0];
+        for pair in profile.windows(2) {
+            let step = glm::distance(&glm::vec2(pair[0].radius, pair[0].y), &glm::vec2(pair[1].radius, pair[1].y));
+            lengths.push(lengths.last().unwrap() + step);
+        }
+        let total = lengths.last().unwrap().max(1e-10);
+
+        let first = (self.vertices.len() / 3) as u32;
+        for (point, length) in profile.iter().zip(&lengths) {
+            for sector in 0..=sectors {
+                let u = sector as f32 / sectors as f32;
+                let (sin, cos) = (u * 2.0 * PI).sin_cos();
+                let position = glm::vec3(point.radius * cos, point.y, -point.radius * sin);
+                let normal = glm::vec3(point.normal.x * cos, point.normal.y, -point.normal.x * sin);
+                self.vertex(position, normal, glm::vec2(u, length / total));
+            }
+        }
+        let stride = sectors as u32 + 1;
+        for ring in 0..profile.len().saturating_sub(1) as u32 {
+            for sector in 0..sectors as u32 {
+                let corner = first + ring * stride + sector;
+                self.quad(corner, corner + 1, corner + stride + 1, corner + stride);
+            }
+        }
+    }
+
+    fn build(self, name: &str) -> Mesh {
+        let vertex_count = self.vertices.len() / 3;
+        let mut mesh = Mesh {
+            name: name.to_string(),
+            vertices: self.vertices,
+            normals: self.normals,
+            texcoords: self.texcoords,
+            tangents: vec![],
+            colors: generate_color_vec([1.0, 1.0, 1.0, 1.0], vertex_count),
+            index_count: self.indices.len() as i32,
+            indices: self.indices,
+            bounds: Bounds::empty(),
+            material: None,
+        };
+        mesh.compute_bounds();
+        mesh.generate_tangents();
+        mesh
+    }
+}
+
+// Points along a circular arc around (0, center_y), from angle from to angle to, measured from straight down
+fn arc(radius: f32, center_y: f32, from: f32, to: f32, steps: usize) -> Vec<ProfilePoint> {
+    (0..=steps).map(|step| {
+        let theta = from + (to - from) * step as f32 / steps as f32;
+        // sin(PI) comes out slightly negative, which would leave the pole a sliver of inside-out triangles
+        let normal = glm::vec2(theta.sin().max(0.0), -theta.cos());
+        ProfilePoint::new(radius * normal.x, center_y + radius * normal.y, normal)
+    }).collect()
+}
+
+impl Mesh {
+    // Every face gets its own four vertices, so that the edges stay sharp and every face shows the whole texture
+    #[allow(dead_code)]
+    pub fn cuboid(size: glm::Vec3) -> Mesh {
+        let half = size / 2.0;
+        let x = glm::vec3(1.0, 0.0, 0.0);
+        let y = glm::vec3(0.0, 1.0, 0.0);
+        let z = glm::vec3(0.0, 0.0, 1.0);
+        // (normal, direction of u, direction of v), where u cross v is the normal
+        let faces = [(x, -z, y), (-x, z, y), (y, x, -z), (-y, x, z), (z, x, y), (-z, -x, y)];
+        let mut builder = MeshBuilder::default();
+        for &(normal, u, v) in &faces {
+            builder.grid(1, 1, |uv| {
+                let direction = normal + u * (uv.x * 2.0 - 1.0) + v * (uv.y * 2.0 - 1.0);
+                (direction.component_mul(&half), normal)
+            });
+        }
+        builder.build("box")
+    }
+
+    // Latitude-longitude sphere. Sectors go around the y axis and stacks from pole to pole
+    #[allow(dead_code)]
+    pub fn uv_sphere(radius: f32, sectors: usize, stacks: usize) -> Mesh {
+        // A single stack would go straight from pole to pole along the axis
+        let stacks = stacks.max(2);
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&arc(radius, 0.0, 0.0, PI, stacks), sectors.max(MIN_SEGMENTS_AROUND));
+        builder.build("uv_sphere")
+    }
+
+    // Sphere made by splitting every triangle of an icosahedron in four, subdivisions times over.
+    // Its triangles are much more even than those of a UV sphere, at the cost of a seam in the texture coordinates.
+    #[allow(dead_code)]
+    pub fn icosphere(radius: f32, subdivisions: usize) -> Mesh {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut points: Vec<glm::Vec3> = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+        ].iter().map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z))).collect();
+        let mut triangles: Vec<[usize; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            // Neighbouring triangles share the point on their common edge
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, points: &mut Vec<glm::Vec3>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push(glm::normalize(&(points[a] + points[b])));
+                    points.len() - 1
+                })
+            };
+            triangles = triangles.iter().flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            }).collect();
+        }
+
+        // The same mapping as the UV sphere, with u running from 0 to 1 counter-clockwise around the y axis
+        let texcoord = |p: &glm::Vec3| {
+            let u = (-p.z).atan2(p.x) / (2.0 * PI);
+            glm::vec2(if u < 0.0 { u + 1.0 } else { u }, (-p.y).clamp(-1.0, 1.0).acos() / PI)
+        };
+        let mut builder = MeshBuilder::default();
+        let mut vertices: HashMap<(usize, bool), u32> = HashMap::new();
+        for triangle in &triangles {
+            let uvs: Vec<glm::Vec2> = triangle.iter().map(|&i| texcoord(&points[i])).collect();
+            // Triangles straddling the seam continue past u = 1 on copies of the vertices at its start
+            let straddles = uvs.iter().any(|uv| uv.x > 0.75) && uvs.iter().any(|uv| uv.x < 0.25);
+            let mut corners = [0; 3];
+            for (k, &i) in triangle.iter().enumerate() {
+                let wrapped = straddles && uvs[k].x < 0.25;
+                corners[k] = *vertices.entry((i, wrapped)).or_insert_with(|| {
+                    let uv = if wrapped { uvs[k] + glm::vec2(1.0, 0.0) } else { uvs[k] };
+                    builder.vertex(points[i] * radius, points[i], uv)
+                });
+            }
+            builder.triangle(corners[0], corners[1], corners[2]);
+        }
+        builder.build("icosphere")
+    }
+
+    #[allow(dead_code)]
+    pub fn cylinder(radius: f32, height: f32, sectors: usize) -> Mesh {
+        let (bottom, top) = (-height / 2.0, height / 2.0);
+        let down = glm::vec2(0.0, -1.0);
+        let up = glm::vec2(0.0, 1.0);
+        let out = glm::vec2(1.0, 0.0);
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&[
+            ProfilePoint::new(0.0, bottom, down),
+            ProfilePoint::new(radius, bottom, down),
+            ProfilePoint::new(radius, bottom, out),
+            ProfilePoint::new(radius, top, out),
+            ProfilePoint::new(radius, top, up),
+            ProfilePoint::new(0.0, top, up),
+        ], sectors.max(MIN_SEGMENTS_AROUND));
+        builder.build("cylinder")
+    }
+
+    // The apex points up. Each sector has its own apex vertex, with the normal of the side along that sector
+    #[allow(dead_code)]
+    pub fn cone(radius: f32, height: f32, sectors: usize) -> Mesh {
+        let (bottom, top) = (-height / 2.0, height / 2.0);
+        let down = glm::vec2(0.0, -1.0);
+        let side = glm::vec2(height, radius);
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&[
+            ProfilePoint::new(0.0, bottom, down),
+            ProfilePoint::new(radius, bottom, down),
+            ProfilePoint::new(radius, bottom, side),
+            ProfilePoint::new(0.0, top, side),
+        ], sectors.max(MIN_SEGMENTS_AROUND));
+        builder.build("cone")
+    }
+
+    // Flat grid in the xz plane facing up, with v running towards -z
+    #[allow(dead_code)]
+    pub fn plane_grid(width: f32, depth: f32, columns: usize, rows: usize) -> Mesh {
+        let mut builder = MeshBuilder::default();
+        builder.grid(columns.max(MIN_SEGMENTS_ALONG), rows.max(MIN_SEGMENTS_ALONG), |uv| {
+            let position = glm::vec3((uv.x - 0.5) * width, 0.0, (0.5 - uv.y) * depth);
+            (position, glm::vec3(0.0, 1.0, 0.0))
+        });
+        builder.build("plane_grid")
+    }
+
+    // Ring around the y axis. major_radius is the distance from the origin to the center of the tube
+    #[allow(dead_code)]
+    pub fn torus(major_radius: f32, minor_radius: f32, major_segments: usize, minor_segments: usize) -> Mesh {
+        let major_segments = major_segments.max(MIN_SEGMENTS_AROUND);
+        let minor_segments = minor_segments.max(MIN_SEGMENTS_AROUND);
+        let profile: Vec<ProfilePoint> = (0..=minor_segments).map(|segment| {
+            let theta = segment as f32 / minor_segments as f32 * 2.0 * PI;
+            let normal = glm::vec2(theta.cos(), theta.sin());
+            ProfilePoint::new(major_radius + minor_radius * normal.x, minor_radius * normal.y, normal)
+        }).collect();
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&profile, major_segments);
+        builder.build("torus")
+    }
+
+    // Cylinder of the given height, capped by hemispheres, so the whole capsule is height + 2 * radius tall
+    #[allow(dead_code)]
+    pub fn capsule(radius: f32, height: f32, sectors: usize, hemisphere_stacks: usize) -> Mesh {
+        let hemisphere_stacks = hemisphere_stacks.max(MIN_SEGMENTS_ALONG);
+        let mut profile = arc(radius, -height / 2.0, 0.0, PI / 2.0, hemisphere_stacks);
+        profile.extend(arc(radius, height / 2.0, PI / 2.0, PI, hemisphere_stacks));
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&profile, sectors.max(MIN_SEGMENTS_AROUND));
+        builder.build("capsule")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::assert_orthonormal_frames;
+
+    fn all_primitives() -> Vec<Mesh> {
+        vec![
+            Mesh::cuboid(glm::vec3(1.0, 2.0, 3.0)),
+            Mesh::uv_sphere(2.0, 16, 8),
+            Mesh::icosphere(2.0, 2),
+            Mesh::cylinder(1.0, 2.0, 12),
+            Mesh::cone(1.0, 2.0, 12),
+            Mesh::plane_grid(4.0, 2.0, 4, 2),
+            Mesh::torus(2.0, 0.5, 16, 8),
+            Mesh::capsule(0.5, 1.0, 12, 4),
+        ]
+    }
+
+    #[test]
+    fn primitives_are_complete_and_wound_outwards() {
+        for mesh in all_primitives() {
+            let vertex_count = mesh.vertices.len() / 3;
+            assert_eq!(mesh.normals.len(), vertex_count * 3, "{}", mesh.name);
+            assert_eq!(mesh.texcoords.len(), vertex_count * 2, "{}", mesh.name);
+            assert_eq!(mesh.colors.len(), vertex_count * 4, "{}", mesh.name);
+            assert_eq!(mesh.index_count as usize, mesh.indices.len(), "{}", mesh.name);
+            assert!(mesh.indices.iter().all(|&i| (i as usize) < vertex_count), "{}", mesh.name);
+            assert_orthonormal_frames(&mesh);
+            for triangle in 0..mesh.indices.len() / 3 {
+                let (face_normal, _) = mesh.triangle_normal_and_angles(triangle);
+                assert!(glm::length(&face_normal) > 0.5, "{} has a degenerate triangle", mesh.name);
+                for &vertex in &mesh.indices[triangle * 3..triangle * 3 + 3] {
+                    let i = vertex as usize * 3;
+                    let normal = glm::make_vec3(&mesh.normals[i..i + 3]);
+                    assert!((glm::length(&normal) - 1.0).abs() < 1e-5, "{}", mesh.name);
+                    assert!(glm::dot(&normal, &face_normal) > 0.0, "{} has a triangle wound inwards", mesh.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spheres_have_the_given_radius() {
+        for mesh in &[Mesh::uv_sphere(2.0, 16, 8), Mesh::icosphere(2.0, 2)] {
+            for vertex in 0..mesh.vertices.len() / 3 {
+                assert!((glm::length(&mesh.position(vertex)) - 2.0).abs() < 1e-5);
+            }
+        }
+        // Every subdivision splits each of the 20 faces of the icosahedron in four
+        assert_eq!(Mesh::icosphere(1.0, 2).index_count, 20 * 16 * 3);
+    }
+
+    #[test]
+    fn primitives_span_their_dimensions() {
+        let extent = |mesh: &Mesh| {
+            let positions: Vec<glm::Vec3> = (0..mesh.vertices.len() / 3).map(|v| mesh.position(v)).collect();
+            let min = positions.iter().fold(glm::vec3(f32::MAX, f32::MAX, f32::MAX), |a, p| glm::min2(&a, p));
+            let max = positions.iter().fold(glm::vec3(f32::MIN, f32::MIN, f32::MIN), |a, p| glm::max2(&a, p));
+            (min, max)
+        };
+        let close = |a: glm::Vec3, b: glm::Vec3| glm::distance(&a, &b) < 1e-5;
+        let (min, max) = extent(&Mesh::cuboid(glm::vec3(1.0, 2.0, 3.0)));
+        assert!(close(min, glm::vec3(-0.5, -1.0, -1.5)) && close(max, glm::vec3(0.5, 1.0, 1.5)));
+        let bounds = Mesh::cuboid(glm::vec3(1.0, 2.0, 3.0)).bounds;
+        assert!(close(bounds.aabb.min, min) && close(bounds.aabb.max, max));
+        assert!((bounds.sphere.radius - glm::length(&glm::vec3(0.5, 1.0, 1.5))).abs() < 1e-5);
+        let (min, max) = extent(&Mesh::capsule(0.5, 1.0, 12, 4));
+        assert!(close(min, glm::vec3(-0.5, -1.0, -0.5)) && close(max, glm::vec3(0.5, 1.0, 0.5)));
+        let (min, max) = extent(&Mesh::torus(2.0, 0.5, 16, 8));
+        assert!(close(min, glm::vec3(-2.5, -0.5, -2.5)) && close(max, glm::vec3(2.5, 0.5, 2.5)));
+        let (min, max) = extent(&Mesh::plane_grid(4.0, 2.0, 4, 2));
+        assert!(close(min, glm::vec3(-2.0, 0.0, -1.0)) && close(max, glm::vec3(2.0, 0.0, 1.0)));
+        assert_eq!(Mesh::plane_grid(4.0, 2.0, 4, 2).vertices.len() / 3, 5 * 3);
+    }
+
+    #[test]
+    fn too_few_segments_are_raised_to_the_minimum() {
+        let meshes = vec![
+            Mesh::uv_sphere(1.0, 0, 0),
+            Mesh::uv_sphere(1.0, 1, 1),
+            Mesh::cylinder(1.0, 2.0, 0),
+            Mesh::cone(1.0, 2.0, 0),
+            Mesh::plane_grid(4.0, 2.0, 0, 0),
+            Mesh::torus(2.0, 0.5, 0, 0),
+            Mesh::capsule(0.5, 1.0, 0, 0),
+        ];
+        for mesh in &meshes {
+            assert!(!mesh.indices.is_empty(), "{} is empty", mesh.name);
+            assert!(mesh.vertices.iter().chain(&mesh.normals).chain(&mesh.texcoords).chain(&mesh.tangents).all(|x| x.is_finite()),
+                "{} has vertices that are not finite", mesh.name);
+        }
+        assert_eq!(Mesh::cylinder(1.0, 2.0, 0).vertices, Mesh::cylinder(1.0, 2.0, 3).vertices);
+        assert_eq!(Mesh::plane_grid(4.0, 2.0, 0, 0).vertices.len() / 3, 4);
+
+        // Sweeping an empty profile makes nothing, rather than counting rings from -1
+        let mut builder = MeshBuilder::default();
+        builder.lathe(&[], 8);
+        assert!(builder.indices.is_empty());
+    }
+}
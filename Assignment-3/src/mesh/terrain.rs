@@ -0,0 +1,376 @@
+// Terrain built from a height field rather than loaded from an OBJ file. The height field is sampled on a regular grid
+// in the xz plane, centered on the origin, with heights running from 0 to the height of the terrain.
+
+extern crate nalgebra_glm as glm;
+
+use image::GenericImageView;
+
+use super::{Mesh, Model, ModelError, Terrain};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainBuilder {
+    // Width along x, greatest height along y and depth along z
+    size: glm::Vec3,
+    // Number of grid cells along x and z. Heightmaps default to one cell per pair of neighbouring pixels
+    resolution: Option<(usize, usize)>,
+}
+
+impl Default for TerrainBuilder {
+    fn default() -> TerrainBuilder {
+        TerrainBuilder {
+            size: glm::vec3(100.0, 10.0, 100.0),
+            resolution: None,
+        }
+    }
+}
+
+impl TerrainBuilder {
+    #[allow(dead_code)]
+    pub fn new() -> TerrainBuilder {
+        TerrainBuilder::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn size(mut self, width: f32, height: f32, depth: f32) -> TerrainBuilder {
+        self.size = glm::vec3(width, height, depth);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn resolution(mut self, columns: usize, rows: usize) -> TerrainBuilder {
+        self.resolution = Some((columns.max(1), rows.max(1)));
+        self
+    }
+
+    // Greyscale image seen from above, with the top of the image towards -z. Black is 0 and white the full height
+    #[allow(dead_code)]
+    pub fn load_heightmap(&self, path: &str) -> Result<Terrain, ModelError> {
+        let image = image::open(path)
+            .map_err(|error| ModelError::Heightmap { path: path.to_string(), error })?;
+        self.build_from_image(path, &image)
+    }
+
+    // Fails for images smaller than 2x2 pixels, which have no cell to interpolate across
+    pub fn build_from_image(&self, name: &str, image: &image::DynamicImage) -> Result<Terrain, ModelError> {
+        if image.width() < 2 || image.height() < 2 {
+            return Err(ModelError::HeightmapTooSmall { name: name.to_string(), width: image.width(), height: image.height() });
+        }
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        // 16 bit heightmaps keep their precision, 8 bit ones are scaled to the same range
+        let color = image.color();
+        let heights: Vec<f32> = if color.bytes_per_pixel() / color.channel_count() > 1 {
+            image.to_luma16().pixels().map(|p| p[0] as f32 / u16::MAX as f32).collect()
+        } else {
+            image.to_luma8().pixels().map(|p| p[0] as f32 / u8::MAX as f32).collect()
+        };
+        let pixel = |x: usize, y: usize| heights[y * width + x];
+        let (columns, rows) = self.resolution.unwrap_or(((width - 1).max(1), (height - 1).max(1)));
+        Ok(self.build_grid(name, columns, rows, |u, v| {
+            // Bilinear interpolation, so that the grid does not have to line up with the pixels
+            let x = u * (width - 1) as f32;
+            let y = (1.0 - v) * (height - 1) as f32;
+            let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+            let top = pixel(x0, y0) * (1.0 - tx) + pixel(x1, y0) * tx;
+            let bottom = pixel(x0, y1) * (1.0 - tx) + pixel(x1, y1) * tx;
+            top * (1.0 - ty) + bottom * ty
+        }))
+    }
+
+    // height maps texture coordinates (u, v) to a height between 0 and 1. u runs along +x and v along -z
+    #[allow(dead_code)]
+    pub fn build_from_fn<F>(&self, height: F) -> Terrain
+        where F: Fn(f32, f32) -> f32
+    {
+        let (columns, rows) = self.resolution.unwrap_or((128, 128));
+        self.build_grid("procedural terrain", columns, rows, height)
+    }
+
+    fn build_grid<F>(&self, name: &str, columns: usize, rows: usize, height: F) -> Terrain
+        where F: Fn(f32, f32) -> f32
+    {
+        let mut mesh = Mesh::plane_grid(self.size.x, self.size.z, columns, rows);
+        mesh.name = name.to_string();
+        let stride = columns + 1;
+        let heights: Vec<f32> = mesh.texcoords.chunks(2).map(|uv| height(uv[0], uv[1]) * self.size.y).collect();
+        for (vertex, &h) in heights.iter().enumerate() {
+            mesh.vertices[vertex * 3 + 1] = h;
+        }
+        mesh.compute_bounds();
+
+        // Central differences, one-sided along the edges. Rows run towards -z
+        let cell = glm::vec2(self.size.x / columns as f32, self.size.z / rows as f32);
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (left, right) = (column.saturating_sub(1), (column + 1).min(columns));
+                let (near, far) = (row.saturating_sub(1), (row + 1).min(rows));
+                let dh_dx = (heights[row * stride + right] - heights[row * stride + left]) / ((right - left) as f32 * cell.x);
+                let dh_dz = (heights[near * stride + column] - heights[far * stride + column]) / ((far - near) as f32 * cell.y);
+                let normal = glm::normalize(&glm::vec3(-dh_dx, 1.0, -dh_dz));
+                let vertex = row * stride + column;
+                mesh.normals[vertex * 3..vertex * 3 + 3].copy_from_slice(normal.as_slice());
+            }
+        }
+        mesh.generate_tangents();
+        Terrain::new(Model { name: name.to_string(), parts: vec![mesh] })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfacePoint {
+    pub height: f32,
+    pub normal: glm::Vec3,
+}
+
+// Buckets the triangles of a mesh by the cells of a regular grid over the xz plane that they overlap,
+// so that finding the triangle under a point only means looking at the few triangles in its cell
+pub struct SurfaceGrid {
+    min: glm::Vec2,
+    cell_size: glm::Vec2,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<u32>>,
+}
+
+impl SurfaceGrid {
+    fn new(mesh: &Mesh) -> SurfaceGrid {
+        let triangle_count = mesh.indices.len() / 3;
+        let flat = |vertex: u32| mesh.position(vertex as usize).xz();
+        let mut min = glm::vec2(f32::MAX, f32::MAX);
+        let mut max = glm::vec2(f32::MIN, f32::MIN);
+        for &vertex in &mesh.indices {
+            min = glm::min2(&min, &flat(vertex));
+            max = glm::max2(&max, &flat(vertex));
+        }
+        // Around two triangles per cell for an evenly tessellated terrain
+        let side = ((triangle_count as f32 / 2.0).sqrt().ceil() as usize).max(1);
+        let cell_size = glm::max2(&((max - min) / side as f32), &glm::vec2(1e-6, 1e-6));
+        let mut grid = SurfaceGrid { min, cell_size, columns: side, rows: side, cells: vec![vec![]; side * side] };
+        for triangle in 0..triangle_count {
+            let corners: Vec<glm::Vec2> = mesh.indices[triangle * 3..triangle * 3 + 3].iter().map(|&v| flat(v)).collect();
+            let low = grid.cell_of(&glm::min2(&glm::min2(&corners[0], &corners[1]), &corners[2]));
+            let high = grid.cell_of(&glm::max2(&glm::max2(&corners[0], &corners[1]), &corners[2]));
+            for row in low.1..=high.1 {
+                for column in low.0..=high.0 {
+                    grid.cells[row * grid.columns + column].push(triangle as u32);
+                }
+            }
+        }
+        grid
+    }
+
+    // Cell containing the point, clamped to the grid
+    fn cell_of(&self, point: &glm::Vec2) -> (usize, usize) {
+        let cell = (point - self.min).component_div(&self.cell_size);
+        let clamp = |value: f32, count: usize| (value.max(0.0) as usize).min(count - 1);
+        (clamp(cell.x, self.columns), clamp(cell.y, self.rows))
+    }
+
+    fn triangles_near(&self, point: &glm::Vec2) -> &[u32] {
+        let (column, row) = self.cell_of(point);
+        &self.cells[row * self.columns + column]
+    }
+}
+
+impl Terrain {
+    pub(super) fn new(model: Model) -> Terrain {
+        let surface = SurfaceGrid::new(&model.parts[0]);
+        Terrain { model, surface }
+    }
+
+    // Height of the terrain straight above or below (x, z), in the coordinates of the mesh. None outside the terrain
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.surface_at(x, z).map(|point| point.height)
+    }
+
+    // Surface normal at (x, z), interpolated between the vertex normals like the shaders do
+    #[allow(dead_code)]
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        self.surface_at(x, z).map(|point| point.normal)
+    }
+
+    // Where overhangs put several triangles above the same point, the highest one is the surface
+    pub fn surface_at(&self, x: f32, z: f32) -> Option<SurfacePoint> {
+        let point = glm::vec2(x, z);
+        let mut best: Option<SurfacePoint> = None;
+        for &triangle in self.surface.triangles_near(&point) {
+            let corners = &self.indices[triangle as usize * 3..triangle as usize * 3 + 3];
+            let p: Vec<glm::Vec3> = corners.iter().map(|&v| self.position(v as usize)).collect();
+            let weights = match barycentric(&point, &p[0].xz(), &p[1].xz(), &p[2].xz()) {
+                Some(weights) => weights,
+                None => continue,
+            };
+            let height = p[0].y * weights.x + p[1].y * weights.y + p[2].y * weights.z;
+            if best.is_some_and(|best| best.height >= height) {
+                continue;
+            }
+            let mut normal: glm::Vec3 = glm::zero();
+            for (k, &vertex) in corners.iter().enumerate() {
+                let i = vertex as usize * 3;
+                normal += glm::make_vec3(&self.normals[i..i + 3]) * weights[k];
+            }
+            best = Some(SurfacePoint { height, normal: glm::normalize(&normal) });
+        }
+        best
+    }
+}
+
+// Weights of a, b and c that make up point, if the point lies within the triangle.
+// Triangles seen edge-on from above have no inside and are skipped
+fn barycentric(point: &glm::Vec2, a: &glm::Vec2, b: &glm::Vec2, c: &glm::Vec2) -> Option<glm::Vec3> {
+    let cross = |u: glm::Vec2, v: glm::Vec2| u.x * v.y - u.y * v.x;
+    let (ab, ac, ap) = (b - a, c - a, point - a);
+    let area = cross(ab, ac);
+    if area.abs() < 1e-12 {
+        return None;
+    }
+    let wb = cross(ap, ac) / area;
+    let wc = cross(ab, ap) / area;
+    let weights = glm::vec3(1.0 - wb - wc, wb, wc);
+    // A little slack, so that points exactly on shared edges are not lost to rounding
+    if weights.min() < -1e-5 {
+        return None;
+    }
+    Some(weights)
+}
+
+// Smooth noise between 0 and 1, as octaves of value noise at doubling frequencies and halving amplitudes.
+// frequency is the number of features of the first octave across the terrain. The same seed gives the same terrain
+#[allow(dead_code)]
+pub fn fractal_noise(seed: u32, octaves: u32, frequency: f32) -> impl Fn(f32, f32) -> f32 {
+    move |u, v| {
+        let (mut sum, mut total, mut amplitude, mut scale) = (0.0, 0.0, 1.0, frequency);
+        for octave in 0..octaves.max(1) {
+            sum += amplitude * value_noise(u * scale, v * scale, seed.wrapping_add(octave));
+            total += amplitude;
+            amplitude *= 0.5;
+            scale *= 2.0;
+        }
+        sum / total
+    }
+}
+
+// Random values at integer lattice points, smoothly interpolated between them
+fn value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let bottom = lattice_value(ix, iy, seed) * (1.0 - tx) + lattice_value(ix + 1, iy, seed) * tx;
+    let top = lattice_value(ix, iy + 1, seed) * (1.0 - tx) + lattice_value(ix + 1, iy + 1, seed) * tx;
+    bottom * (1.0 - ty) + top * ty
+}
+
+fn lattice_value(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1) ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sloped_height_field_gives_tilted_normals() {
+        let terrain = TerrainBuilder::new().size(20.0, 5.0, 10.0).resolution(4, 2).build_from_fn(|u, _| u);
+        assert_eq!(terrain.vertices.len() / 3, 5 * 3);
+        assert_eq!(terrain.index_count, 4 * 2 * 6);
+        let expected = glm::normalize(&glm::vec3(-5.0 / 20.0, 1.0, 0.0));
+        for vertex in 0..terrain.vertices.len() / 3 {
+            let p = terrain.position(vertex);
+            assert!((p.y - (p.x + 10.0) / 20.0 * 5.0).abs() < 1e-5);
+            assert!(glm::distance(&glm::make_vec3(&terrain.normals[vertex * 3..vertex * 3 + 3]), &expected) < 1e-5);
+        }
+        assert_eq!(terrain.texcoords.len() / 2, terrain.vertices.len() / 3);
+        crate::mesh::tests::assert_orthonormal_frames(&terrain);
+    }
+
+    #[test]
+    fn heightmaps_are_seen_from_above() {
+        // White along the top row of the image, black along the bottom
+        let image = image::GrayImage::from_fn(3, 2, |_, y| image::Luma([if y == 0 { 255 } else { 0 }]));
+        let path = std::env::temp_dir().join("gloom_heightmap_test.png");
+        image.save(&path).unwrap();
+        let terrain = TerrainBuilder::new().size(2.0, 4.0, 2.0).load_heightmap(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(terrain.vertices.len() / 3, 3 * 2);
+        for vertex in 0..terrain.vertices.len() / 3 {
+            let p = terrain.position(vertex);
+            let expected = if p.z < 0.0 { 4.0 } else { 0.0 };
+            assert!((p.y - expected).abs() < 1e-5, "{} should be at height {}", p, expected);
+        }
+
+        match TerrainBuilder::new().load_heightmap("./does/not/exist.png") {
+            Err(ModelError::Heightmap { path, .. }) => assert_eq!(path, "./does/not/exist.png"),
+            _ => panic!("Expected heightmap error"),
+        }
+    }
+
+    #[test]
+    fn heightmaps_need_a_cell_and_name_their_mesh() {
+        for &(width, height) in &[(0, 0), (1, 5), (5, 1)] {
+            let image = image::DynamicImage::new_luma8(width, height);
+            match TerrainBuilder::new().build_from_image("tiny", &image) {
+                Err(ModelError::HeightmapTooSmall { name, width: w, height: h }) => {
+                    assert_eq!((name.as_str(), w, h), ("tiny", width, height));
+                },
+                _ => panic!("Expected a too small error for {}x{}", width, height),
+            }
+        }
+
+        let terrain = TerrainBuilder::new().build_from_image("moon", &image::DynamicImage::new_luma8(2, 2)).unwrap();
+        assert_eq!(terrain.name, "moon");
+    }
+
+    #[test]
+    fn height_and_normal_are_interpolated_across_triangles() {
+        let terrain = TerrainBuilder::new().size(20.0, 5.0, 10.0).resolution(7, 3).build_from_fn(|u, _| u);
+        for &(x, z) in &[(0.0, 0.0), (-9.9, 4.9), (3.3, -1.7), (10.0, -5.0)] {
+            let point = terrain.surface_at(x, z).unwrap();
+            assert!((point.height - (x + 10.0) / 20.0 * 5.0).abs() < 1e-4);
+            assert!(glm::distance(&point.normal, &glm::normalize(&glm::vec3(-5.0 / 20.0, 1.0, 0.0))) < 1e-5);
+        }
+        assert_eq!(terrain.height_at(10.5, 0.0), None);
+        assert_eq!(terrain.height_at(0.0, -5.5), None);
+
+        // Lands exactly on the vertices of a bumpy terrain
+        let terrain = TerrainBuilder::new().size(8.0, 3.0, 8.0).resolution(8, 8).build_from_fn(fractal_noise(1, 3, 2.0));
+        for vertex in 0..terrain.vertices.len() / 3 {
+            let p = terrain.position(vertex);
+            assert!((terrain.height_at(p.x, p.z).unwrap() - p.y).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn loaded_terrain_can_be_queried_too() {
+        // The left side of the tent rises from (-1, 0) to the ridge at (0, 1)
+        let terrain = Terrain::from_model(crate::mesh::tests::load_obj_str(crate::mesh::tests::TENT)).unwrap();
+        assert!((terrain.height_at(-0.25, 0.5).unwrap() - 0.75).abs() < 1e-5);
+        assert!((terrain.height_at(0.5, 0.5).unwrap() - 0.5).abs() < 1e-5);
+        let left = glm::normalize(&glm::vec3(-1.0, 1.0, 0.0));
+        assert!(glm::distance(&terrain.normal_at(-0.25, 0.5).unwrap(), &left) < 1e-5);
+        assert_eq!(terrain.height_at(0.0, 2.0), None);
+    }
+
+    #[test]
+    fn fractal_noise_is_repeatable_and_in_range() {
+        let noise = fractal_noise(7, 5, 4.0);
+        let other = fractal_noise(8, 5, 4.0);
+        let mut differs = false;
+        for i in 0..100 {
+            let (u, v) = (i as f32 * 0.013, i as f32 * 0.029);
+            let h = noise(u, v);
+            assert!((0.0..=1.0).contains(&h));
+            assert_eq!(h, fractal_noise(7, 5, 4.0)(u, v));
+            differs |= h != other(u, v);
+        }
+        assert!(differs);
+    }
+}
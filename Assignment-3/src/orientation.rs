@@ -0,0 +1,103 @@
+extern crate nalgebra_glm as glm;
+
+// Order in which Euler angle rotation matrices are multiplied together. XYZ means Rx * Ry * Rz, so the rotation about
+// z is applied to the vertices first. The angles themselves are always given as (x, y, z), whatever the order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+pub fn from_euler(angles: &glm::Vec3, order: EulerOrder) -> glm::Quat {
+    let x = glm::quat_angle_axis(angles.x, &glm::vec3(1.0, 0.0, 0.0));
+    let y = glm::quat_angle_axis(angles.y, &glm::vec3(0.0, 1.0, 0.0));
+    let z = glm::quat_angle_axis(angles.z, &glm::vec3(0.0, 0.0, 1.0));
+    match order {
+        EulerOrder::XYZ => x * y * z,
+        EulerOrder::XZY => x * z * y,
+        EulerOrder::YXZ => y * x * z,
+        EulerOrder::YZX => y * z * x,
+        EulerOrder::ZXY => z * x * y,
+        EulerOrder::ZYX => z * y * x,
+    }
+}
+
+pub fn from_axis_angle(axis: &glm::Vec3, angle: f32) -> glm::Quat {
+    glm::quat_angle_axis(angle, axis)
+}
+
+// Orientation that turns the -z axis, which is the forward direction of our models, towards direction,
+// keeping the y axis as close to up as possible. Direction and up must not be parallel.
+pub fn look_towards(direction: &glm::Vec3, up: &glm::Vec3) -> glm::Quat {
+    let forward = glm::normalize(direction);
+    let right = glm::normalize(&glm::cross(&forward, up));
+    let up = glm::cross(&right, &forward);
+    let basis = glm::mat3(
+        right.x, up.x, -forward.x,
+        right.y, up.y, -forward.y,
+        right.z, up.z, -forward.z,
+    );
+    glm::quat_normalize(&glm::mat3_to_quat(&basis))
+}
+
+// Spherical linear interpolation along the shortest arc between two orientations
+#[allow(dead_code)]
+pub fn slerp(from: &glm::Quat, to: &glm::Quat, t: f32) -> glm::Quat {
+    let from = glm::quat_normalize(from);
+    let mut to = glm::quat_normalize(to);
+    let mut cos_theta = glm::quat_dot(&from, &to);
+    // q and -q describe the same orientation, flip one of them to avoid going the long way around
+    if cos_theta < 0.0 {
+        to = -to;
+        cos_theta = -cos_theta;
+    }
+    // sin(theta) approaches zero for nearly identical orientations, where a normalized lerp is just as good
+    if cos_theta > 0.9995 {
+        return glm::quat_normalize(&glm::quat_lerp(&from, &to, t));
+    }
+    let theta = cos_theta.acos();
+    (from * ((1.0 - t) * theta).sin() + to * (t * theta).sin()) / theta.sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_vec3_eq(actual: &glm::Vec3, expected: &glm::Vec3) {
+        assert!(glm::distance(actual, expected) < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn euler_order_decides_which_rotation_is_applied_first() {
+        let angles = glm::vec3(FRAC_PI_2, FRAC_PI_2, 0.0);
+        let v = glm::vec3(0.0, 0.0, 1.0);
+        // XYZ: rotate about y first, (0, 0, 1) -> (1, 0, 0), which rotation about x leaves alone
+        assert_vec3_eq(&glm::quat_rotate_vec3(&from_euler(&angles, EulerOrder::XYZ), &v), &glm::vec3(1.0, 0.0, 0.0));
+        // YXZ: rotate about x first, (0, 0, 1) -> (0, -1, 0), which rotation about y leaves alone
+        assert_vec3_eq(&glm::quat_rotate_vec3(&from_euler(&angles, EulerOrder::YXZ), &v), &glm::vec3(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn look_towards_points_forward_axis_along_direction() {
+        let direction = glm::vec3(1.0, 0.0, 1.0);
+        let q = look_towards(&direction, &glm::vec3(0.0, 1.0, 0.0));
+        assert_vec3_eq(&glm::quat_rotate_vec3(&q, &glm::vec3(0.0, 0.0, -1.0)), &glm::normalize(&direction));
+        assert_vec3_eq(&glm::quat_rotate_vec3(&q, &glm::vec3(0.0, 1.0, 0.0)), &glm::vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn slerp_takes_the_shortest_arc() {
+        let from = from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), 0.0);
+        let to = -from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), FRAC_PI_2);
+        let halfway = slerp(&from, &to, 0.5);
+        let rotated = glm::quat_rotate_vec3(&halfway, &glm::vec3(0.0, 0.0, 1.0));
+        let expected = glm::vec3((FRAC_PI_2 / 2.0).sin(), 0.0, (FRAC_PI_2 / 2.0).cos());
+        assert_vec3_eq(&rotated, &expected);
+    }
+}
@@ -0,0 +1,262 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bounds::Aabb;
+use crate::mesh::Mesh;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    // Unit length
+    pub direction: glm::Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction: glm::normalize(&direction) }
+    }
+
+    // Ray from the camera through a pixel, given in window coordinates with (0, 0) in the top left corner.
+    // It starts on the near plane, so nothing between the camera and the near plane can be hit.
+    pub fn from_screen(pixel: glm::Vec2, viewport_size: glm::Vec2, view: &glm::Mat4, projection: &glm::Mat4) -> Ray {
+        let ndc = glm::vec2(2.0 * pixel.x / viewport_size.x - 1.0, 1.0 - 2.0 * pixel.y / viewport_size.y);
+        let inverse = glm::inverse(&(projection * view));
+        let unproject = |depth: f32| {
+            let point = inverse * glm::vec4(ndc.x, ndc.y, depth, 1.0);
+            point.xyz() / point.w
+        };
+        let near = unproject(-1.0);
+        Ray::new(near, unproject(1.0) - near)
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // The same ray in the space that matrix transforms into. The direction is renormalized, so distances along the
+    // transformed ray are not distances along this one
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Ray {
+        let origin = (matrix * glm::vec4(self.origin.x, self.origin.y, self.origin.z, 1.0)).xyz();
+        let direction = (matrix * glm::vec4(self.direction.x, self.direction.y, self.direction.z, 0.0)).xyz();
+        Ray::new(origin, direction)
+    }
+
+    // Distance to where the ray enters the box, or 0 if it starts inside it
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        if aabb.is_empty() {
+            return None;
+        }
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            // Dividing by a zero component gives infinities, which the comparisons below handle
+            let inverse = 1.0 / self.direction[axis];
+            let t0 = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let t1 = (aabb.max[axis] - self.origin[axis]) * inverse;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    // Möller-Trumbore. Both sides of the triangle can be hit
+    pub fn intersect_triangle(&self, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> Option<f32> {
+        let (ab, ac) = (b - a, c - a);
+        let p = glm::cross(&self.direction, &ac);
+        let determinant = glm::dot(&ab, &p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let to_origin = self.origin - a;
+        let u = glm::dot(&to_origin, &p) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = glm::cross(&to_origin, &ab);
+        let v = glm::dot(&self.direction, &q) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = glm::dot(&ac, &q) / determinant;
+        if distance >= 0.0 { Some(distance) } else { None }
+    }
+
+    // Closest triangle of the mesh hit by the ray, which must be in the coordinates of the mesh
+    pub fn intersect_mesh(&self, mesh: &Mesh) -> Option<f32> {
+        self.intersect_aabb(&mesh.bounds.aabb)?;
+        let corner = |index: u32| glm::make_vec3(&mesh.vertices[index as usize * 3..index as usize * 3 + 3]);
+        mesh.indices.chunks(3)
+            .filter_map(|triangle| self.intersect_triangle(&corner(triangle[0]), &corner(triangle[1]), &corner(triangle[2])))
+            .fold(None, |closest: Option<f32>, distance| Some(closest.map_or(distance, |c| c.min(distance))))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    pub node: NodeId,
+    // Along the ray, in world space
+    pub distance: f32,
+    pub point: glm::Vec3,
+}
+
+// Closest drawable node under node_id hit by a world space ray. Subtrees and nodes are first tested against the world
+// space bounds from the last `update_node_transformations`, and then against the triangles of the mesh that mesh_of
+// gives for the node. Nodes it gives no mesh for are hit wherever their bounding box is, and nodes with neither
+// mesh nor bounds cannot be hit at all.
+pub fn pick<'a, F>(scene: &SceneGraph, node_id: NodeId, ray: &Ray, mesh_of: F) -> Option<Hit>
+    where F: Fn(NodeId) -> Option<&'a Mesh>
+{
+    let mut closest = None;
+    pick_node(scene, node_id, ray, &mesh_of, &mut closest);
+    closest
+}
+
+fn pick_node<'a, F>(scene: &SceneGraph, node_id: NodeId, ray: &Ray, mesh_of: &F, closest: &mut Option<Hit>)
+    where F: Fn(NodeId) -> Option<&'a Mesh>
+{
+    let node = &scene[node_id];
+    let is_closer = |distance: f32, closest: &Option<Hit>| match closest {
+        Some(hit) => distance < hit.distance,
+        None => true,
+    };
+    // Unbounded subtrees have drawable nodes somewhere outside their bounds, so the ray has to go looking for them
+    match ray.intersect_aabb(&node.subtree_bounds().aabb) {
+        Some(distance) if is_closer(distance, closest) => {},
+        _ if !node.subtree_is_bounded() => {},
+        _ => return,
+    }
+
+    if node.index_count > 0 {
+        // Nodes without bounds can only be hit through their mesh
+        let box_distance = ray.intersect_aabb(&node.world_bounds().aabb);
+        if box_distance.is_some() || node.world_bounds().is_empty() {
+            let distance = match mesh_of(node_id) {
+                Some(mesh) => {
+                    let local_ray = ray.transformed(&glm::inverse(&node.current_transformation_matrix));
+                    local_ray.intersect_mesh(mesh).map(|local_distance| {
+                        let local_point = local_ray.at(local_distance);
+                        let point = (node.current_transformation_matrix * glm::vec4(local_point.x, local_point.y, local_point.z, 1.0)).xyz();
+                        glm::distance(&point, &ray.origin)
+                    })
+                },
+                None => box_distance,
+            };
+            if let Some(distance) = distance {
+                if is_closer(distance, closest) {
+                    *closest = Some(Hit { node: node_id, distance, point: ray.at(distance) });
+                }
+            }
+        }
+    }
+
+    for &child in node.children() {
+        pick_node(scene, child, ray, mesh_of, closest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::{update_node_transformations, SceneNode};
+
+    fn assert_vec3_eq(actual: &glm::Vec3, expected: &glm::Vec3) {
+        assert!(glm::distance(actual, expected) < 1e-4, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn screen_rays_go_through_their_pixel() {
+        let view = glm::look_at(&glm::vec3(0.0, 0.0, 10.0), &glm::zero(), &glm::vec3(0.0, 1.0, 0.0));
+        let projection = glm::perspective(800.0 / 600.0, 1.0, 1.0, 100.0);
+        let center = Ray::from_screen(glm::vec2(400.0, 300.0), glm::vec2(800.0, 600.0), &view, &projection);
+        assert_vec3_eq(&center.origin, &glm::vec3(0.0, 0.0, 9.0));
+        assert_vec3_eq(&center.direction, &glm::vec3(0.0, 0.0, -1.0));
+
+        // The top left corner is up and to the left, and projects back onto that corner
+        let corner = Ray::from_screen(glm::vec2(0.0, 0.0), glm::vec2(800.0, 600.0), &view, &projection);
+        assert!(corner.direction.x < 0.0 && corner.direction.y > 0.0);
+        let clip = projection * view * glm::vec4(corner.at(50.0).x, corner.at(50.0).y, corner.at(50.0).z, 1.0);
+        assert!((clip.x / clip.w + 1.0).abs() < 1e-4 && (clip.y / clip.w - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rays_hit_boxes_and_triangles() {
+        let ray = Ray::new(glm::vec3(0.0, 0.0, 5.0), glm::vec3(0.0, 0.0, -1.0));
+        let aabb = Aabb { min: glm::vec3(-1.0, -1.0, -1.0), max: glm::vec3(1.0, 1.0, 1.0) };
+        assert_eq!(ray.intersect_aabb(&aabb), Some(4.0));
+        assert_eq!(Ray::new(glm::zero(), glm::vec3(0.0, 1.0, 0.0)).intersect_aabb(&aabb), Some(0.0));
+        assert_eq!(Ray::new(glm::vec3(0.0, 3.0, 5.0), glm::vec3(0.0, 0.0, -1.0)).intersect_aabb(&aabb), None);
+        assert_eq!(Ray::new(glm::vec3(0.0, 0.0, -5.0), glm::vec3(0.0, 0.0, -1.0)).intersect_aabb(&aabb), None);
+
+        let (a, b, c) = (glm::vec3(-1.0, -1.0, 0.0), glm::vec3(1.0, -1.0, 0.0), glm::vec3(0.0, 1.0, 0.0));
+        assert_eq!(ray.intersect_triangle(&a, &b, &c), Some(5.0));
+        assert_eq!(Ray::new(glm::vec3(2.0, 0.0, 5.0), glm::vec3(0.0, 0.0, -1.0)).intersect_triangle(&a, &b, &c), None);
+    }
+
+    #[test]
+    fn picking_finds_the_closest_mesh_triangle() {
+        let sphere = Mesh::uv_sphere(1.0, 16, 8);
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let mut near = SceneNode::from_vao(1, sphere.index_count);
+        near.set_local_bounds(sphere.bounds);
+        near.set_position(glm::vec3(0.0, 0.0, -5.0));
+        near.set_scale(glm::vec3(2.0, 2.0, 2.0));
+        let near = scene.add_node(near);
+        let mut far = SceneNode::from_vao(1, sphere.index_count);
+        far.set_local_bounds(sphere.bounds);
+        far.set_position(glm::vec3(0.0, 0.0, -20.0));
+        let far = scene.add_node(far);
+        scene.add_child(root, far);
+        scene.add_child(root, near);
+        update_node_transformations(&mut scene, root, &glm::identity());
+        let mesh_of = |_| Some(&sphere);
+
+        // The sphere of radius 2 at z = -5 is hit at z = -3, as close as the tessellation allows
+        let hit = pick(&scene, root, &Ray::new(glm::zero(), glm::vec3(0.0, 0.0, -1.0)), mesh_of).unwrap();
+        assert_eq!(hit.node, near);
+        assert!((hit.distance - 3.0).abs() < 0.05);
+        assert_vec3_eq(&hit.point, &glm::vec3(0.0, 0.0, -hit.distance));
+
+        // Passing by the near sphere, inside its bounding box but outside the sphere itself
+        let ray = Ray::new(glm::vec3(2.5, 2.5, 0.0), glm::vec3(-0.125, -0.125, -1.0));
+        let hit = pick(&scene, root, &ray, mesh_of).unwrap();
+        assert_eq!(hit.node, far);
+
+        // Without triangles, the bounding box is as good as it gets
+        let hit = pick(&scene, root, &Ray::new(glm::vec3(1.8, 1.8, 0.0), glm::vec3(0.0, 0.0, -1.0)), |_| None).unwrap();
+        assert_eq!(hit.node, near);
+        assert!((hit.distance - 3.0).abs() < 1e-4);
+
+        assert_eq!(pick(&scene, root, &Ray::new(glm::zero(), glm::vec3(0.0, 0.0, 1.0)), mesh_of), None);
+    }
+
+    #[test]
+    fn nodes_without_bounds_are_picked_by_their_mesh() {
+        let sphere = Mesh::uv_sphere(1.0, 16, 8);
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let mut bounded = SceneNode::from_vao(1, sphere.index_count);
+        bounded.set_local_bounds(sphere.bounds);
+        bounded.set_position(glm::vec3(0.0, 0.0, -20.0));
+        let bounded = scene.add_node(bounded);
+        let group = scene.add_node(SceneNode::new());
+        let mut unbounded = SceneNode::from_vao(1, sphere.index_count);
+        unbounded.set_position(glm::vec3(0.0, 0.0, -5.0));
+        let unbounded = scene.add_node(unbounded);
+        scene.add_child(root, bounded);
+        scene.add_child(root, group);
+        scene.add_child(group, unbounded);
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        // The root's bounds only cover the far sphere, yet the unbounded one in front of it is the one hit
+        let ray = Ray::new(glm::zero(), glm::vec3(0.0, 0.0, -1.0));
+        let hit = pick(&scene, root, &ray, |_| Some(&sphere)).unwrap();
+        assert_eq!(hit.node, unbounded);
+        assert!((hit.distance - 4.0).abs() < 0.05);
+
+        // With no mesh there is nothing to hit it with
+        assert_eq!(pick(&scene, root, &ray, |_| None).unwrap().node, bounded);
+    }
+}
@@ -0,0 +1,375 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::Bounds;
+use crate::mesh::Material;
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// Declarative description of a scene, stored as RON or JSON depending on the file extension.
+// Meshes are declared once and referred to by name from the nodes that draw them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    pub root: NodeDescription,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MeshDescription {
+    pub name: String,
+    // Path to the OBJ file, relative to the working directory
+    pub path: String,
+    // Name of the object within the OBJ file. If left out, the file must contain a single object
+    #[serde(default)]
+    pub object: Option<String>,
+    // Only used if the OBJ file has no material for the object
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    // Path to a diffuse texture image. If left out, the diffuse texture of the material is used, if it has one
+    #[serde(default)]
+    pub texture: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct NodeDescription {
+    pub name: String,
+    pub mesh: Option<String>,
+    pub position: [f32; 3],
+    // Quaternion as [x, y, z, w]
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    pub reference_point: [f32; 3],
+    pub children: Vec<NodeDescription>,
+}
+
+impl Default for NodeDescription {
+    fn default() -> NodeDescription {
+        NodeDescription {
+            name: String::new(),
+            mesh: None,
+            position: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+            reference_point: [0.0; 3],
+            children: vec![],
+        }
+    }
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneFormat {
+    Ron,
+    Json,
+}
+
+impl SceneFormat {
+    fn from_path(path: &Path) -> Result<SceneFormat, SceneFileError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => Ok(SceneFormat::Ron),
+            Some("json") => Ok(SceneFormat::Json),
+            _ => Err(SceneFileError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    UnknownFormat(String),
+    Parse(String),
+    Serialize(String),
+    UnknownMesh(String),
+    MeshLoad { mesh: String, message: String },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneFileError::Io(e) => write!(f, "Failed to access scene file: {}", e),
+            SceneFileError::UnknownFormat(path) => write!(f, "Unknown scene file format, expected .ron or .json: {}", path),
+            SceneFileError::Parse(e) => write!(f, "Failed to parse scene file: {}", e),
+            SceneFileError::Serialize(e) => write!(f, "Failed to serialize scene: {}", e),
+            SceneFileError::UnknownMesh(name) => write!(f, "Scene node refers to undeclared mesh {}", name),
+            SceneFileError::MeshLoad { mesh, message } => write!(f, "Failed to load mesh {}: {}", mesh, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(e: std::io::Error) -> SceneFileError {
+        SceneFileError::Io(e)
+    }
+}
+
+// What a declared mesh became once uploaded to the GPU
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadedMesh {
+    pub vao_id: u32,
+    pub index_count: i32,
+    pub material: Material,
+    // 0 if the mesh is not textured
+    pub texture_id: u32,
+    pub bounds: Bounds,
+}
+
+// What became of a SceneDescription after instantiating it into a SceneGraph.
+// Remembers which mesh each node draws, which the graph itself has no notion of, so the scene can be saved again.
+pub struct LoadedScene {
+    pub root: NodeId,
+    pub meshes: Vec<MeshDescription>,
+    node_meshes: HashMap<NodeId, String>,
+}
+
+impl SceneDescription {
+    pub fn load(path: &str) -> Result<SceneDescription, SceneFileError> {
+        let format = SceneFormat::from_path(Path::new(path))?;
+        let source = std::fs::read_to_string(path)?;
+        SceneDescription::parse(&source, format)
+    }
+
+    pub fn parse(source: &str, format: SceneFormat) -> Result<SceneDescription, SceneFileError> {
+        match format {
+            SceneFormat::Ron => ron::from_str(source).map_err(|e| SceneFileError::Parse(e.to_string())),
+            SceneFormat::Json => serde_json::from_str(source).map_err(|e| SceneFileError::Parse(e.to_string())),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn save(&self, path: &str) -> Result<(), SceneFileError> {
+        let format = SceneFormat::from_path(Path::new(path))?;
+        std::fs::write(path, self.to_string(format)?)?;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn to_string(&self, format: SceneFormat) -> Result<String, SceneFileError> {
+        match format {
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| SceneFileError::Serialize(e.to_string())),
+            SceneFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|e| SceneFileError::Serialize(e.to_string())),
+        }
+    }
+
+    // Builds the node tree into scene. Every declared mesh is handed to upload_mesh once, which should return the
+    // VAO, index count and material to draw it with. Keeping the GL work in the callback lets scenes be built without a context.
+    // If anything fails, the nodes built so far are removed again.
+    pub fn instantiate<F>(&self, scene: &mut SceneGraph, mut upload_mesh: F) -> Result<LoadedScene, SceneFileError>
+        where F: FnMut(&MeshDescription) -> Result<UploadedMesh, String>
+    {
+        let mut uploaded = HashMap::new();
+        for mesh in &self.meshes {
+            let uploaded_mesh = upload_mesh(mesh)
+                .map_err(|message| SceneFileError::MeshLoad { mesh: mesh.name.clone(), message })?;
+            uploaded.insert(mesh.name.clone(), uploaded_mesh);
+        }
+
+        let mut node_meshes = HashMap::new();
+        let root = instantiate_node(&self.root, scene, &uploaded, &mut node_meshes)?;
+        Ok(LoadedScene {
+            root,
+            meshes: self.meshes.clone(),
+            node_meshes,
+        })
+    }
+}
+
+fn instantiate_node(
+    description: &NodeDescription,
+    scene: &mut SceneGraph,
+    uploaded: &HashMap<String, UploadedMesh>,
+    node_meshes: &mut HashMap<NodeId, String>,
+) -> Result<NodeId, SceneFileError> {
+    let mut node = match &description.mesh {
+        Some(mesh) => {
+            let uploaded_mesh = uploaded.get(mesh)
+                .ok_or_else(|| SceneFileError::UnknownMesh(mesh.clone()))?;
+            let mut node = SceneNode::from_vao(uploaded_mesh.vao_id, uploaded_mesh.index_count);
+            node.material = uploaded_mesh.material.clone();
+            node.texture_id = uploaded_mesh.texture_id;
+            node.set_local_bounds(uploaded_mesh.bounds);
+            node
+        },
+        None => SceneNode::new(),
+    }.with_name(&description.name);
+    node.set_position(glm::make_vec3(&description.position));
+    node.set_rotation(glm::make_quat(&description.rotation));
+    node.set_scale(glm::make_vec3(&description.scale));
+    node.set_reference_point(glm::make_vec3(&description.reference_point));
+
+    let id = scene.add_node(node);
+    if let Some(mesh) = &description.mesh {
+        node_meshes.insert(id, mesh.clone());
+    }
+    for child_description in &description.children {
+        match instantiate_node(child_description, scene, uploaded, node_meshes) {
+            Ok(child) => scene.add_child(id, child),
+            Err(e) => {
+                scene.remove(id).unwrap();
+                return Err(e);
+            },
+        }
+    }
+    Ok(id)
+}
+
+impl LoadedScene {
+    // Name of the declared mesh the node draws, if any
+    pub fn mesh_of(&self, node: NodeId) -> Option<&str> {
+        self.node_meshes.get(&node).map(|mesh| mesh.as_str())
+    }
+
+    // Captures the current state of the loaded tree, including any changes made to it since loading
+    #[allow(dead_code)]
+    pub fn to_description(&self, scene: &SceneGraph) -> SceneDescription {
+        SceneDescription {
+            meshes: self.meshes.clone(),
+            root: self.describe_node(scene, self.root),
+        }
+    }
+
+    fn describe_node(&self, scene: &SceneGraph, id: NodeId) -> NodeDescription {
+        let node = &scene[id];
+        let rotation = node.rotation();
+        NodeDescription {
+            name: node.name.clone(),
+            mesh: self.node_meshes.get(&id).cloned(),
+            position: node.position().into(),
+            rotation: [rotation.i, rotation.j, rotation.k, rotation.w],
+            scale: node.scale().into(),
+            reference_point: node.reference_point().into(),
+            children: node.children().iter().map(|&child| self.describe_node(scene, child)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_scene() -> SceneDescription {
+        SceneDescription {
+            meshes: vec![
+                MeshDescription {
+                    name: "terrain".to_string(),
+                    path: "./resources/lunarsurface.obj".to_string(),
+                    object: None,
+                    color: [1.0, 1.0, 1.0, 1.0],
+                    texture: Some("./resources/regolith.png".to_string()),
+                },
+                MeshDescription {
+                    name: "tail_rotor".to_string(),
+                    path: "./resources/helicopter.obj".to_string(),
+                    object: Some("Tail_Rotor_tail_rotor".to_string()),
+                    color: [0.1, 0.3, 0.1, 1.0],
+                    texture: None,
+                },
+            ],
+            root: NodeDescription {
+                name: "root".to_string(),
+                children: vec![NodeDescription {
+                    name: "terrain".to_string(),
+                    mesh: Some("terrain".to_string()),
+                    scale: [2.0, 0.5, 2.0],
+                    children: vec![NodeDescription {
+                        name: "tail_rotor".to_string(),
+                        mesh: Some("tail_rotor".to_string()),
+                        position: [1.0, 2.0, 3.0],
+                        rotation: [0.0, 1.0, 0.0, 0.0],
+                        reference_point: [0.35, 2.3, 10.4],
+                        ..NodeDescription::default()
+                    }],
+                    ..NodeDescription::default()
+                }],
+                ..NodeDescription::default()
+            },
+        }
+    }
+
+    // Hands out fake VAO and texture ids instead of touching GL
+    fn fake_upload(mesh: &MeshDescription) -> Result<UploadedMesh, String> {
+        Ok(UploadedMesh {
+            vao_id: mesh.name.len() as u32,
+            index_count: 3,
+            material: Material::default(),
+            texture_id: mesh.texture.as_ref().map_or(0, |path| path.len() as u32),
+            bounds: Bounds::from_points(&[glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)]),
+        })
+    }
+
+    #[test]
+    fn text_formats_round_trip() {
+        let description = example_scene();
+        for &format in &[SceneFormat::Ron, SceneFormat::Json] {
+            let text = description.to_string(format).unwrap();
+            assert_eq!(SceneDescription::parse(&text, format).unwrap(), description);
+        }
+    }
+
+    #[test]
+    fn scene_graph_round_trips() {
+        let description = example_scene();
+        let mut scene = SceneGraph::new();
+        let loaded = description.instantiate(&mut scene, fake_upload).unwrap();
+
+        assert_eq!(scene.len(), 3);
+        let tail_rotor = scene.find_path("root/terrain/tail_rotor").unwrap();
+        assert_eq!(scene[tail_rotor].vao_id, "tail_rotor".len() as u32);
+        assert_eq!(scene[tail_rotor].reference_point(), glm::vec3(0.35, 2.3, 10.4));
+        assert_eq!(scene[tail_rotor].texture_id, 0);
+        assert_eq!(loaded.mesh_of(tail_rotor), Some("tail_rotor"));
+        assert_eq!(loaded.mesh_of(loaded.root), None);
+        let terrain = scene.find_path("root/terrain").unwrap();
+        assert_eq!(scene[terrain].texture_id, "./resources/regolith.png".len() as u32);
+        assert_eq!(loaded.to_description(&scene), description);
+
+        scene[tail_rotor].set_position(glm::vec3(4.0, 5.0, 6.0));
+        let saved = loaded.to_description(&scene);
+        assert_eq!(saved.root.children[0].children[0].position, [4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn omitted_fields_take_defaults() {
+        let text = r#"(
+            meshes: [(name: "terrain", path: "terrain.obj")],
+            root: (name: "root", children: [(name: "terrain", mesh: Some("terrain"))]),
+        )"#;
+        let description = SceneDescription::parse(text, SceneFormat::Ron).unwrap();
+        assert_eq!(description.meshes[0].color, [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(description.meshes[0].texture, None);
+        assert_eq!(description.root.children[0].scale, [1.0, 1.0, 1.0]);
+        assert_eq!(description.root.children[0].rotation, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bundled_scene_parses() {
+        let description = SceneDescription::load("./scenes/lunar_helicopter.ron").unwrap();
+        let mut scene = SceneGraph::new();
+        description.instantiate(&mut scene, fake_upload).unwrap();
+        assert!(scene.find_path("root/terrain/heli_body/tail_rotor").is_some());
+    }
+
+    #[test]
+    fn unknown_mesh_leaves_scene_untouched() {
+        let mut description = example_scene();
+        description.root.children[0].children[0].mesh = Some("main_rotor".to_string());
+        let mut scene = SceneGraph::new();
+        match description.instantiate(&mut scene, fake_upload) {
+            Err(SceneFileError::UnknownMesh(name)) => assert_eq!(name, "main_rotor"),
+            _ => panic!("Expected unknown mesh error"),
+        }
+        assert!(scene.is_empty());
+    }
+}
@@ -1,58 +1,203 @@
 extern crate nalgebra_glm as glm;
 
-use std::mem::ManuallyDrop;
-use std::pin::Pin;
-
-// Used to crete an unholy abomination upon which you should not cast your gaze.
-// This ended up being a necessity due to wanting to keep the code written by students as "straight forward" as possible
-// It is very very double plus ungood Rust, and intentionally leaks memory like a sieve. But it works, and you're more than welcome to pretend it doesn't exist!
-// In case you're curious about how it works; It allocates memory on the heap (Box), promises to prevent it from being moved or deallocated until dropped (Pin) 
-// and finally prevents the compiler from dropping it automatically at all (ManuallyDrop). If that sounds like a janky solution, it's because it is.
-// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of having what I arbitrarily decided to be the required level of
-// simplicity of use.
-type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+use std::ops::{Index, IndexMut};
+
+use crate::bounds::Bounds;
+use crate::mesh::Material;
+use crate::orientation::{self, EulerOrder};
+
+// Nodes are owned by a SceneGraph arena and referred to through NodeIds. An id carries the generation of the slot it
+// was handed out for, so an id to a removed node can never silently alias whatever node ends up reusing its slot.
+// Dropping the SceneGraph drops every node it owns, so scenes can be built, torn down and rebuilt without leaking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SceneGraphError {
+    // The id refers to a node that has been removed, or to a different graph
+    InvalidNode(NodeId),
+    // The requested parent is the node itself or one of its descendants
+    Cycle { child: NodeId, parent: NodeId },
+}
 
 pub struct SceneNode {
-    pub position: glm::Vec3,
-    pub rotation: glm::Vec3,
-    pub scale: glm::Vec3,
-    pub reference_point: glm::Vec3,
+    pub name: String,
+
+    position: glm::Vec3,
+    rotation: glm::Quat,
+    scale: glm::Vec3,
+    reference_point: glm::Vec3,
 
     pub current_transformation_matrix: glm::Mat4,
+    pub current_normal_matrix: glm::Mat3,
+
+    // The current matrices are only recomputed when the node's own transform has changed since the last update,
+    // or when the transformation it inherits from its parent has
+    dirty: bool,
+    // Set on a node and all of its ancestors whenever the node is changed, so that updates can skip the subtrees where
+    // nothing was. Whenever it is set on a node, it is set on all of its ancestors as well.
+    subtree_dirty: bool,
+    inherited_transformation: glm::Mat4,
 
     pub vao_id: u32,
     pub index_count: i32,
+    pub material: Material,
+    // Diffuse texture to draw the node with, or 0 for none
+    pub texture_id: u32,
+
+    // Bounds of the node's own mesh in its own space, and in world space as of the last update
+    local_bounds: Bounds,
+    world_bounds: Bounds,
+    // World space bounds of the node's mesh together with everything below it
+    subtree_bounds: Bounds,
+    // Whether every drawable node in the subtree has local bounds, so that subtree_bounds covers all of it
+    subtree_bounded: bool,
 
-    pub children: Vec<*mut SceneNode>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
 }
 
 impl SceneNode {
-    pub fn new() -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position: glm::zero(),
-            rotation: glm::zero(),
-            scale: glm::vec3(1.0, 1.0, 1.0),
-            reference_point: glm::zero(),
-            current_transformation_matrix: glm::identity(),
-            vao_id: 0,
-            index_count: -1,
-            children: vec![],
-        })))
+    pub fn new() -> SceneNode {
+        SceneNode::from_vao(0, -1)
     }
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+    pub fn from_vao(vao_id: u32, index_count: i32) -> SceneNode {
+        SceneNode {
+            name: String::new(),
             position: glm::zero(),
-            rotation: glm::zero(),
+            rotation: glm::quat_identity(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
             current_transformation_matrix: glm::identity(),
+            current_normal_matrix: glm::identity(),
+            dirty: true,
+            subtree_dirty: true,
+            inherited_transformation: glm::identity(),
             vao_id, index_count,
+            material: Material::default(),
+            texture_id: 0,
+            local_bounds: Bounds::empty(),
+            world_bounds: Bounds::empty(),
+            subtree_bounds: Bounds::empty(),
+            subtree_bounded: true,
+            parent: None,
             children: vec![],
-        })))
+        }
+    }
+    pub fn with_name(mut self, name: &str) -> SceneNode {
+        self.name = name.to_string();
+        self
+    }
+    #[allow(dead_code)]
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+    pub fn position(&self) -> glm::Vec3 {
+        self.position
+    }
+    pub fn rotation(&self) -> glm::Quat {
+        self.rotation
+    }
+    pub fn scale(&self) -> glm::Vec3 {
+        self.scale
+    }
+    pub fn reference_point(&self) -> glm::Vec3 {
+        self.reference_point
+    }
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+        self.dirty = true;
+    }
+    pub fn set_rotation(&mut self, rotation: glm::Quat) {
+        self.rotation = glm::quat_normalize(&rotation);
+        self.dirty = true;
+    }
+    pub fn set_rotation_euler(&mut self, angles: glm::Vec3, order: EulerOrder) {
+        self.set_rotation(orientation::from_euler(&angles, order));
     }
-    pub fn add_child(&mut self, child: &SceneNode) {
-        self.children.push(child as *const SceneNode as *mut SceneNode)
+    #[allow(dead_code)]
+    pub fn set_rotation_axis_angle(&mut self, axis: glm::Vec3, angle: f32) {
+        self.set_rotation(orientation::from_axis_angle(&axis, angle));
     }
+    // Turns the node's forward (-z) axis towards direction, see `orientation::look_towards`
+    #[allow(dead_code)]
+    pub fn look_towards(&mut self, direction: glm::Vec3, up: glm::Vec3) {
+        self.set_rotation(orientation::look_towards(&direction, &up));
+    }
+    pub fn set_scale(&mut self, scale: glm::Vec3) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+    pub fn set_reference_point(&mut self, reference_point: glm::Vec3) {
+        self.reference_point = reference_point;
+        self.dirty = true;
+    }
+    pub fn set_local_bounds(&mut self, bounds: Bounds) {
+        self.local_bounds = bounds;
+        self.dirty = true;
+    }
+    pub fn world_bounds(&self) -> Bounds {
+        self.world_bounds
+    }
+    pub fn subtree_bounds(&self) -> Bounds {
+        self.subtree_bounds
+    }
+    // Drawable nodes without local bounds could be anywhere, so a subtree with any of them in it is only partly
+    // covered by its subtree_bounds
+    pub fn subtree_is_bounded(&self) -> bool {
+        self.subtree_bounded
+    }
+    #[cfg(test)]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    // Transformation from the node's own space into its parent's space.
+    // Rotation and scale are both applied about the reference point, before translating by the position.
+    pub fn local_transformation(&self) -> glm::Mat4 {
+        let origin = glm::mat4(
+            1.0, 0.0, 0.0, self.reference_point[0],
+            0.0, 1.0, 0.0, self.reference_point[1],
+            0.0, 0.0, 1.0, self.reference_point[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let rotation = glm::quat_to_mat4(&self.rotation);
+
+        let scale = glm::mat4(
+            self.scale[0], 0.0, 0.0, 0.0,
+            0.0, self.scale[1], 0.0, 0.0,
+            0.0, 0.0, self.scale[2], 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let inverse_origin = glm::mat4(
+            1.0, 0.0, 0.0, -self.reference_point[0],
+            0.0, 1.0, 0.0, -self.reference_point[1],
+            0.0, 0.0, 1.0, -self.reference_point[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let translation = glm::mat4(
+            1.0, 0.0, 0.0, self.position[0],
+            0.0, 1.0, 0.0, self.position[1],
+            0.0, 0.0, 1.0, self.position[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        translation * origin * rotation * scale * inverse_origin
+    }
+    // Matrix for transforming normals into world space. Uses the inverse transpose of the current transformation,
+    // as the transformation itself would skew normals away from their surface under non-uniform scaling.
+    pub fn normal_matrix(&self) -> glm::Mat3 {
+        glm::inverse_transpose(glm::mat4_to_mat3(&self.current_transformation_matrix))
+    }
+    #[allow(dead_code)]
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
         let matrix_string = format!(
@@ -69,23 +214,32 @@ impl SceneNode {
         );
         println!(
 "SceneNode {{
+    Name:      {}
     VAO:       {}
     Indices:   {}
+    Texture:   {}
     Children:  {}
     Position:  [{:.2}, {:.2}, {:.2}]
-    Rotation:  [{:.2}, {:.2}, {:.2}]
+    Rotation:  [{:.2}, {:.2}, {:.2}, {:.2}]
+    Scale:     [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
     Current Transformation Matrix: {}
 }}",
+            self.name,
             self.vao_id,
             self.index_count,
+            self.texture_id,
             self.children.len(),
             self.position.x,
             self.position.y,
             self.position.z,
-            self.rotation.x,
-            self.rotation.y,
-            self.rotation.z,
+            self.rotation.i,
+            self.rotation.j,
+            self.rotation.k,
+            self.rotation.w,
+            self.scale.x,
+            self.scale.y,
+            self.scale.z,
             self.reference_point.x,
             self.reference_point.y,
             self.reference_point.z,
@@ -93,3 +247,694 @@ impl SceneNode {
         );
     }
 }
+
+struct Slot {
+    generation: u32,
+    node: Option<SceneNode>,
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph::default()
+    }
+
+    // Takes ownership of a node and returns the id it can be reached through. The node starts out without a parent.
+    // Whatever it was last updated with, such as when it was taken out by `remove`, is recomputed on the next update.
+    pub fn add_node(&mut self, mut node: SceneNode) -> NodeId {
+        node.parent = None;
+        node.children.clear();
+        node.dirty = true;
+        node.subtree_dirty = true;
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId { index, generation: slot.generation }
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, node: Some(node) });
+                NodeId { index: self.slots.len() - 1, generation: 0 }
+            },
+        }
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        self.slots.get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    // Any change made through the returned node is picked up by the next `update_node_transformations`
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.get(id)?;
+        self.mark_subtree_dirty(id);
+        self.get_mut_unmarked(id)
+    }
+
+    fn get_mut_unmarked(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.slots.get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    // Marks the node and its ancestors, stopping at the first one that is marked already, as its ancestors must be too
+    fn mark_subtree_dirty(&mut self, id: NodeId) {
+        let mut current = Some(id);
+        while let Some(next) = current {
+            let node = self.slots[next.index].node.as_mut().unwrap();
+            if node.subtree_dirty {
+                break;
+            }
+            node.subtree_dirty = true;
+            current = node.parent;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Iterates over every node in the graph, in no particular hierarchical order
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &SceneNode)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.node.as_ref().map(|node| (NodeId { index, generation: slot.generation }, node))
+        })
+    }
+
+    // Nodes without a parent
+    pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.iter().filter(|(_, node)| node.parent.is_none()).map(|(id, _)| id)
+    }
+
+    // First node with the given name. Names need not be unique, see `find_all`
+    #[allow(dead_code)]
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter().find(|(_, node)| node.name == name).map(|(id, _)| id)
+    }
+
+    #[allow(dead_code)]
+    pub fn find_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = NodeId> + 'a {
+        self.iter().filter(move |(_, node)| node.name == name).map(|(id, _)| id)
+    }
+
+    // Resolves a path of node names separated by '/', such as "root/terrain/heli_body/tail_rotor".
+    // The first segment names one of the root nodes, and each segment after it names a child of the node before it.
+    pub fn find_path(&self, path: &str) -> Option<NodeId> {
+        let path = path.trim_start_matches('/');
+        let (root_name, rest) = path.split_once('/').unwrap_or((path, ""));
+        self.roots()
+            .filter(|&root| self[root].name == root_name)
+            .find_map(|root| self.find_path_from(root, rest))
+    }
+
+    // Resolves a path of node names relative to start, see `find_path`
+    pub fn find_path_from(&self, start: NodeId, path: &str) -> Option<NodeId> {
+        let mut current = start;
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            current = *self.get(current)?.children.iter()
+                .find(|&&child| self[child].name == segment)?;
+        }
+        Some(current)
+    }
+
+    // The names of the node and its ancestors up to its root, separated by '/', in the form accepted by `find_path`
+    #[allow(dead_code)]
+    pub fn path_of(&self, id: NodeId) -> Option<String> {
+        let mut current = self.get(id)?;
+        let mut names = vec![current.name.as_str()];
+        while let Some(parent) = current.parent {
+            current = &self[parent];
+            names.push(current.name.as_str());
+        }
+        names.reverse();
+        Some(names.join("/"))
+    }
+
+    // Attaches child below parent, detaching it from its previous parent first.
+    // Panics if either id is invalid or if the attachment would create a cycle, see `reparent` for a fallible version.
+    pub fn add_child(&mut self, parent: NodeId, child: NodeId) {
+        if let Err(e) = self.reparent(child, Some(parent)) {
+            panic!("Failed to add child to scene node: {:?}", e);
+        }
+    }
+
+    // Moves child below new_parent, or makes it a root node if new_parent is None
+    pub fn reparent(&mut self, child: NodeId, new_parent: Option<NodeId>) -> Result<(), SceneGraphError> {
+        if !self.contains(child) {
+            return Err(SceneGraphError::InvalidNode(child));
+        }
+        if let Some(parent) = new_parent {
+            if !self.contains(parent) {
+                return Err(SceneGraphError::InvalidNode(parent));
+            }
+            if self.is_ancestor_or_self(child, parent) {
+                return Err(SceneGraphError::Cycle { child, parent });
+            }
+        }
+
+        self.detach(child);
+        if let Some(parent) = new_parent {
+            self[parent].children.push(child);
+        }
+        // The child inherits a different transformation now
+        let node = self.get_mut_unmarked(child).unwrap();
+        node.parent = new_parent;
+        node.dirty = true;
+        node.subtree_dirty = false;
+        self.mark_subtree_dirty(child);
+        Ok(())
+    }
+
+    // Removes the node and its whole subtree from the graph, returning the removed nodes in pre-order.
+    // Ids referring to the removed nodes become invalid.
+    pub fn remove(&mut self, id: NodeId) -> Result<Vec<SceneNode>, SceneGraphError> {
+        if !self.contains(id) {
+            return Err(SceneGraphError::InvalidNode(id));
+        }
+        self.detach(id);
+
+        let mut removed = vec![];
+        let mut stack = vec![id];
+        while let Some(next) = stack.pop() {
+            let slot = &mut self.slots[next.index];
+            let node = slot.node.take().unwrap();
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(next.index);
+            stack.extend(node.children.iter().rev());
+            removed.push(node);
+        }
+        Ok(removed)
+    }
+
+    // Drops every node in the graph. All previously handed out ids become invalid.
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.node.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index);
+            }
+        }
+    }
+
+    fn is_ancestor_or_self(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self[node].parent;
+        }
+        false
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        if let Some(parent) = self[id].parent.take() {
+            self[parent].children.retain(|&c| c != id);
+        }
+    }
+}
+
+impl Index<NodeId> for SceneGraph {
+    type Output = SceneNode;
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.get(id).expect("Invalid or removed scene node id")
+    }
+}
+
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.get_mut(id).expect("Invalid or removed scene node id")
+    }
+}
+
+// Brings the matrices and bounds of node_id and its subtree up to date with the changes made since the last update,
+// and returns how many nodes it visited. Only the changed nodes and their ancestors are visited, unless the
+// transformation handed to node_id changed, which changes everything below it.
+pub fn update_node_transformations(scene: &mut SceneGraph, node_id: NodeId, transformation_so_far: &glm::Mat4) -> usize {
+    let inherited_changed = scene[node_id].inherited_transformation != *transformation_so_far;
+    update_subtree(scene, node_id, transformation_so_far, inherited_changed)
+}
+
+fn update_subtree(scene: &mut SceneGraph, node_id: NodeId, transformation_so_far: &glm::Mat4, inherited_changed: bool)
+    -> usize
+{
+    let root = scene.get_mut_unmarked(node_id).expect("Invalid or removed scene node id");
+    if !inherited_changed && !root.subtree_dirty {
+        return 0;
+    }
+    let changed = inherited_changed || root.dirty;
+    if changed {
+        root.current_transformation_matrix = transformation_so_far * root.local_transformation();
+        root.current_normal_matrix = root.normal_matrix();
+        root.world_bounds = root.local_bounds.transformed(&root.current_transformation_matrix);
+        root.inherited_transformation = *transformation_so_far;
+        root.dirty = false;
+    }
+    root.subtree_dirty = false;
+    let current_transformation_matrix = root.current_transformation_matrix;
+
+    // Recurse, gathering the bounds of the subtree on the way back up. Skipped children keep the bounds they had
+    let mut visited = 1;
+    let mut subtree_bounds = root.world_bounds;
+    let mut subtree_bounded = root.index_count <= 0 || !root.local_bounds.is_empty();
+    for i in 0..scene[node_id].children().len() {
+        let child = scene[node_id].children()[i];
+        visited += update_subtree(scene, child, &current_transformation_matrix, changed);
+        subtree_bounds = subtree_bounds.union(&scene[child].subtree_bounds);
+        subtree_bounded &= scene[child].subtree_bounded;
+    }
+    let root = scene.get_mut_unmarked(node_id).unwrap();
+    root.subtree_bounds = subtree_bounds;
+    root.subtree_bounded = subtree_bounded;
+    visited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_mat4_eq(actual: &glm::Mat4, expected: &glm::Mat4) {
+        for i in 0..16 {
+            assert!((actual[i] - expected[i]).abs() < 1e-5, "\nexpected {}\nactual {}", expected, actual);
+        }
+    }
+
+    fn assert_mat3_eq(actual: &glm::Mat3, expected: &glm::Mat3) {
+        for i in 0..9 {
+            assert!((actual[i] - expected[i]).abs() < 1e-5, "\nexpected {}\nactual {}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn scale_is_applied_about_reference_point() {
+        let mut node = SceneNode::new();
+        node.set_scale(glm::vec3(2.0, 3.0, 4.0));
+        node.set_reference_point(glm::vec3(1.0, 1.0, 1.0));
+
+        // x' = 2(x - 1) + 1, y' = 3(y - 1) + 1, z' = 4(z - 1) + 1
+        let expected = glm::mat4(
+            2.0, 0.0, 0.0, -1.0,
+            0.0, 3.0, 0.0, -2.0,
+            0.0, 0.0, 4.0, -3.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        assert_mat4_eq(&node.local_transformation(), &expected);
+        // The reference point itself stays put
+        let p = node.local_transformation() * glm::vec4(1.0, 1.0, 1.0, 1.0);
+        assert!(glm::distance(&p.xyz(), &glm::vec3(1.0, 1.0, 1.0)) < 1e-5);
+    }
+
+    #[test]
+    fn scale_is_applied_before_rotation_and_translation() {
+        let mut node = SceneNode::new();
+        node.set_position(glm::vec3(5.0, 0.0, 0.0));
+        node.set_rotation_euler(glm::vec3(0.0, 0.0, std::f32::consts::FRAC_PI_2), EulerOrder::XYZ);
+        node.set_scale(glm::vec3(2.0, 1.0, 1.0));
+
+        // Scale x by 2, then rotate 90 degrees about z: (x, y) -> (-y, 2x), then translate x by 5
+        let expected = glm::mat4(
+            0.0, -1.0, 0.0, 5.0,
+            2.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        assert_mat4_eq(&node.local_transformation(), &expected);
+    }
+
+    #[test]
+    fn parent_scale_is_composed_into_children() {
+        let mut scene = SceneGraph::new();
+        let parent = scene.add_node(SceneNode::new());
+        let child = scene.add_node(SceneNode::new());
+        scene.add_child(parent, child);
+        scene[parent].set_scale(glm::vec3(2.0, 2.0, 2.0));
+        scene[child].set_position(glm::vec3(1.0, 0.0, 0.0));
+        scene[child].set_scale(glm::vec3(1.0, 0.5, 1.0));
+
+        update_node_transformations(&mut scene, parent, &glm::identity());
+
+        let expected = glm::mat4(
+            2.0, 0.0, 0.0, 2.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 2.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        assert_mat4_eq(&scene[child].current_transformation_matrix, &expected);
+        assert_mat3_eq(&scene[child].current_normal_matrix, &glm::mat3(
+            0.5, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 0.5,
+        ));
+    }
+
+    #[test]
+    fn world_bounds_are_propagated_up_the_hierarchy() {
+        let unit_cube = Bounds::from_points(&[glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)]);
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let parent = scene.add_node(SceneNode::new());
+        let child = scene.add_node(SceneNode::new());
+        scene.add_child(root, parent);
+        scene.add_child(parent, child);
+        scene[parent].set_local_bounds(unit_cube);
+        scene[parent].set_scale(glm::vec3(2.0, 2.0, 2.0));
+        scene[child].set_local_bounds(unit_cube);
+        scene[child].set_position(glm::vec3(5.0, 0.0, 0.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        // The child is scaled by its parent too, so it ends up at x = 10, with a half size of 2
+        assert_eq!(scene[child].world_bounds().aabb.min, glm::vec3(8.0, -2.0, -2.0));
+        assert_eq!(scene[parent].world_bounds().aabb.max, glm::vec3(2.0, 2.0, 2.0));
+        assert_eq!(scene[parent].subtree_bounds().aabb.min, glm::vec3(-2.0, -2.0, -2.0));
+        assert_eq!(scene[parent].subtree_bounds().aabb.max, glm::vec3(12.0, 2.0, 2.0));
+        assert_eq!(scene[root].subtree_bounds(), scene[parent].subtree_bounds());
+        assert!(scene[root].world_bounds().is_empty());
+
+        // Moving only the child still updates the bounds above it
+        scene[child].set_position(glm::vec3(0.0, -5.0, 0.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+        assert_eq!(scene[root].subtree_bounds().aabb.min, glm::vec3(-2.0, -12.0, -2.0));
+        assert_eq!(scene[root].subtree_bounds().aabb.max, glm::vec3(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn only_changed_subtrees_are_recomputed() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let left = scene.add_node(SceneNode::new());
+        let right = scene.add_node(SceneNode::new());
+        let right_child = scene.add_node(SceneNode::new());
+        scene.add_child(root, left);
+        scene.add_child(root, right);
+        scene.add_child(right, right_child);
+        update_node_transformations(&mut scene, root, &glm::identity());
+        assert!(!scene[right_child].is_dirty());
+
+        // Tag the cached matrix of an untouched node, so that any recomputation of it would be visible
+        let tag = glm::translation(&glm::vec3(0.0, 0.0, 42.0));
+        scene[left].current_transformation_matrix = tag;
+
+        scene[right].set_position(glm::vec3(1.0, 2.0, 3.0));
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        assert_mat4_eq(&scene[left].current_transformation_matrix, &tag);
+        // The changed node is recomputed, and its children along with it
+        let expected = glm::translation(&glm::vec3(1.0, 2.0, 3.0));
+        assert_mat4_eq(&scene[right].current_transformation_matrix, &expected);
+        assert_mat4_eq(&scene[right_child].current_transformation_matrix, &expected);
+
+        // So is everything when the transformation handed to the root changes
+        update_node_transformations(&mut scene, root, &glm::translation(&glm::vec3(1.0, 0.0, 0.0)));
+        assert_mat4_eq(&scene[left].current_transformation_matrix, &glm::translation(&glm::vec3(1.0, 0.0, 0.0)));
+        assert_mat4_eq(&scene[right_child].current_transformation_matrix, &glm::translation(&glm::vec3(2.0, 2.0, 3.0)));
+    }
+
+    #[test]
+    fn clean_subtrees_of_a_large_tree_are_skipped() {
+        const DEPTH: usize = 5;
+        const BRANCHING: usize = 4;
+
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let mut level = vec![root];
+        for depth in 0..DEPTH {
+            let mut next_level = vec![];
+            for &parent in &level {
+                for i in 0..BRANCHING {
+                    let mut node = SceneNode::new();
+                    node.set_position(glm::vec3(i as f32, depth as f32, 1.0));
+                    let id = scene.add_node(node);
+                    scene.add_child(parent, id);
+                    next_level.push(id);
+                }
+            }
+            level = next_level;
+        }
+        let node_count = scene.len();
+        assert_eq!(update_node_transformations(&mut scene, root, &glm::identity()), node_count);
+        assert_eq!(update_node_transformations(&mut scene, root, &glm::identity()), 0);
+
+        // Changing a leaf visits the path down to it, and nothing else
+        let leaf = *level.last().unwrap();
+        scene[leaf].set_position(glm::vec3(0.0, 0.0, 7.0));
+        assert_eq!(update_node_transformations(&mut scene, root, &glm::identity()), DEPTH + 1);
+        let leaf_position = scene[leaf].current_transformation_matrix.column(3).xyz();
+        assert_eq!(leaf_position, glm::vec3(3.0 * DEPTH as f32 - 3.0, 6.0, DEPTH as f32 + 6.0));
+
+        // Reparenting moves the node under its new parent on the next update
+        let first_leaf = level[0];
+        scene.reparent(leaf, Some(first_leaf)).unwrap();
+        assert_eq!(update_node_transformations(&mut scene, root, &glm::identity()), 2 * (DEPTH + 1) - 1);
+        assert_eq!(scene[leaf].current_transformation_matrix.column(3).xyz(), glm::vec3(0.0, 10.0, 12.0));
+
+        // Changing what is handed to the root changes every node
+        assert_eq!(update_node_transformations(&mut scene, root, &glm::translation(&glm::vec3(1.0, 0.0, 0.0))), node_count);
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture bench_`
+    #[test]
+    #[ignore]
+    fn bench_dirty_update_of_deep_and_wide_tree() {
+        const DEPTH: usize = 7;
+        const BRANCHING: usize = 5;
+        const FRAMES: u32 = 50;
+
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let mut level = vec![root];
+        for depth in 0..DEPTH {
+            let mut next_level = vec![];
+            for &parent in &level {
+                for i in 0..BRANCHING {
+                    let mut node = SceneNode::new();
+                    node.set_position(glm::vec3(i as f32, depth as f32, 1.0));
+                    node.set_rotation_euler(glm::vec3(0.1, 0.2, 0.3), EulerOrder::XYZ);
+                    node.set_reference_point(glm::vec3(0.5, 0.5, 0.5));
+                    let id = scene.add_node(node);
+                    scene.add_child(parent, id);
+                    next_level.push(id);
+                }
+            }
+            level = next_level;
+        }
+        let animated = *level.last().unwrap();
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        // A different matrix handed to the root every frame makes every node recompute, as before the dirty flags
+        let before = std::time::Instant::now();
+        let mut full_visits = 0;
+        for frame in 0..FRAMES {
+            scene[animated].set_rotation_euler(glm::vec3(frame as f32, 0.0, 0.0), EulerOrder::XYZ);
+            let shift = glm::translation(&glm::vec3(frame as f32 + 1.0, 0.0, 0.0));
+            full_visits += update_node_transformations(&mut scene, root, &shift);
+        }
+        let full = before.elapsed();
+
+        // The same animation with the root staying put only visits the path down to the animated node
+        update_node_transformations(&mut scene, root, &glm::identity());
+        let before = std::time::Instant::now();
+        let mut dirty_visits = 0;
+        for frame in 0..FRAMES {
+            scene[animated].set_rotation_euler(glm::vec3(frame as f32, 0.0, 0.0), EulerOrder::XYZ);
+            dirty_visits += update_node_transformations(&mut scene, root, &glm::identity());
+        }
+        let dirty = before.elapsed();
+
+        println!("{} nodes, {} frames", scene.len(), FRAMES);
+        println!("Recomputing every node: {:.3}ms per frame, {} nodes visited",
+            full.as_secs_f64() * 1e3 / FRAMES as f64, full_visits / FRAMES as usize);
+        println!("Recomputing dirty nodes: {:.3}ms per frame, {} nodes visited",
+            dirty.as_secs_f64() * 1e3 / FRAMES as f64, dirty_visits / FRAMES as usize);
+        assert!(dirty < full);
+    }
+
+    #[test]
+    fn nodes_can_be_found_by_name_and_path() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new().with_name("root"));
+        let terrain = scene.add_node(SceneNode::new().with_name("terrain"));
+        let body = scene.add_node(SceneNode::new().with_name("heli_body"));
+        let tail_rotor = scene.add_node(SceneNode::new().with_name("tail_rotor"));
+        let other_tail_rotor = scene.add_node(SceneNode::new().with_name("tail_rotor"));
+        scene.add_child(root, terrain);
+        scene.add_child(terrain, body);
+        scene.add_child(body, tail_rotor);
+        scene.add_child(terrain, other_tail_rotor);
+
+        assert_eq!(scene.find("heli_body"), Some(body));
+        assert_eq!(scene.find("main_rotor"), None);
+        assert_eq!(scene.find_all("tail_rotor").count(), 2);
+
+        assert_eq!(scene.find_path("root/terrain/heli_body/tail_rotor"), Some(tail_rotor));
+        assert_eq!(scene.find_path("root/terrain/tail_rotor"), Some(other_tail_rotor));
+        assert_eq!(scene.find_path("terrain/heli_body/tail_rotor"), None);
+        assert_eq!(scene.find_path_from(body, "tail_rotor"), Some(tail_rotor));
+        assert_eq!(scene.path_of(tail_rotor).as_deref(), Some("root/terrain/heli_body/tail_rotor"));
+
+        assert_eq!(scene.roots().collect::<Vec<_>>(), vec![root]);
+        assert_eq!(scene.iter().count(), 5);
+        scene.remove(body).unwrap();
+        assert_eq!(scene.iter().count(), 3);
+        assert_eq!(scene.find_path("root/terrain/heli_body/tail_rotor"), None);
+    }
+
+    #[test]
+    fn paths_round_trip_with_several_roots() {
+        let mut scene = SceneGraph::new();
+        let mut nodes = vec![];
+        for root_name in &["level", "ui"] {
+            let root = scene.add_node(SceneNode::new().with_name(root_name));
+            let panel = scene.add_node(SceneNode::new().with_name("panel"));
+            let button = scene.add_node(SceneNode::new().with_name("button"));
+            scene.add_child(root, panel);
+            scene.add_child(panel, button);
+            nodes.extend(&[root, panel, button]);
+        }
+
+        // The same names below both roots, told apart by the root's name
+        for &node in &nodes {
+            let path = scene.path_of(node).unwrap();
+            assert_eq!(scene.find_path(&path), Some(node), "{}", path);
+        }
+        assert_eq!(scene.path_of(nodes[0]).as_deref(), Some("level"));
+        assert_eq!(scene.path_of(nodes[5]).as_deref(), Some("ui/panel/button"));
+        assert_eq!(scene.find_path("panel/button"), None);
+    }
+
+    #[test]
+    fn normal_matrix_keeps_normals_perpendicular_under_non_uniform_scale() {
+        let mut node = SceneNode::new();
+        node.set_scale(glm::vec3(1.0, 4.0, 1.0));
+        node.current_transformation_matrix = node.local_transformation();
+
+        let expected = glm::mat3(
+            1.0, 0.0, 0.0,
+            0.0, 0.25, 0.0,
+            0.0, 0.0, 1.0,
+        );
+        assert_mat3_eq(&node.normal_matrix(), &expected);
+
+        // The plane x + y = 0 becomes x + y/4 = 0, which has normal (4, 1, 0)
+        let normal = glm::normalize(&(node.normal_matrix() * glm::vec3(1.0, 1.0, 0.0)));
+        let tangent = (node.current_transformation_matrix * glm::vec4(1.0, -1.0, 0.0, 0.0)).xyz();
+        assert!(glm::dot(&normal, &tangent).abs() < 1e-5);
+        assert!(glm::distance(&normal, &glm::normalize(&glm::vec3(4.0, 1.0, 0.0))) < 1e-5);
+    }
+
+    #[test]
+    fn reparenting_below_a_descendant_is_a_cycle() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let child = scene.add_node(SceneNode::new());
+        let grandchild = scene.add_node(SceneNode::new());
+        scene.add_child(root, child);
+        scene.add_child(child, grandchild);
+
+        assert_eq!(scene.reparent(root, Some(grandchild)), Err(SceneGraphError::Cycle { child: root, parent: grandchild }));
+        assert_eq!(scene.reparent(child, Some(child)), Err(SceneGraphError::Cycle { child, parent: child }));
+        // A rejected reparent leaves the hierarchy as it was
+        assert_eq!(scene[root].parent(), None);
+        assert_eq!(scene[child].parent(), Some(root));
+        assert_eq!(scene[grandchild].parent(), Some(child));
+    }
+
+    #[test]
+    fn stale_ids_do_not_alias_nodes_reusing_their_slot() {
+        let mut scene = SceneGraph::new();
+        let old = scene.add_node(SceneNode::new().with_name("old"));
+        scene.remove(old).unwrap();
+        let new = scene.add_node(SceneNode::new().with_name("new"));
+        assert_eq!(new.index, old.index);
+
+        assert!(scene.get(old).is_none());
+        assert!(scene.get_mut(old).is_none());
+        assert_eq!(scene.remove(old).err(), Some(SceneGraphError::InvalidNode(old)));
+        assert_eq!(scene.reparent(old, None), Err(SceneGraphError::InvalidNode(old)));
+        assert_eq!(scene.reparent(new, Some(old)), Err(SceneGraphError::InvalidNode(old)));
+        assert_eq!(scene[new].name, "new");
+        assert_eq!(scene.len(), 1);
+    }
+
+    #[test]
+    fn remove_takes_the_whole_subtree_and_detaches_it() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new().with_name("root"));
+        let sibling = scene.add_node(SceneNode::new().with_name("sibling"));
+        let branch = scene.add_node(SceneNode::new().with_name("branch"));
+        let leaf_a = scene.add_node(SceneNode::new().with_name("a"));
+        let leaf_b = scene.add_node(SceneNode::new().with_name("b"));
+        scene.add_child(root, sibling);
+        scene.add_child(root, branch);
+        scene.add_child(branch, leaf_a);
+        scene.add_child(branch, leaf_b);
+
+        let removed = scene.remove(branch).unwrap();
+        let names: Vec<&str> = removed.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, ["branch", "a", "b"]);
+
+        assert_eq!(scene[root].children(), [sibling]);
+        for id in [branch, leaf_a, leaf_b].iter() {
+            assert!(!scene.contains(*id));
+        }
+        assert_eq!(scene.len(), 2);
+    }
+
+    #[test]
+    fn removed_nodes_are_recomputed_when_added_again() {
+        let unit_cube = Bounds::from_points(&[glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)]);
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let branch = scene.add_node(SceneNode::new());
+        let node = scene.add_node(SceneNode::new());
+        let leaf = scene.add_node(SceneNode::new());
+        scene.add_child(root, branch);
+        scene.add_child(branch, node);
+        scene.add_child(node, leaf);
+        scene[node].set_local_bounds(unit_cube);
+        scene[leaf].set_position(glm::vec3(10.0, 0.0, 0.0));
+        scene[leaf].set_local_bounds(unit_cube);
+        update_node_transformations(&mut scene, root, &glm::identity());
+
+        // The node comes back on its own, without the leaf it was last updated with, and is handed the same matrix
+        let mut removed = scene.remove(branch).unwrap();
+        let node = scene.add_node(removed.remove(1));
+        assert_eq!(update_node_transformations(&mut scene, node, &glm::identity()), 1);
+        assert_eq!(scene[node].subtree_bounds(), unit_cube);
+    }
+
+    #[test]
+    fn reparenting_to_none_detaches_the_node() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(SceneNode::new());
+        let child = scene.add_node(SceneNode::new());
+        let grandchild = scene.add_node(SceneNode::new());
+        scene.add_child(root, child);
+        scene.add_child(child, grandchild);
+
+        scene.reparent(child, None).unwrap();
+        assert_eq!(scene[child].parent(), None);
+        assert!(scene[root].children().is_empty());
+        // The detached node keeps its own subtree and becomes a root of the graph
+        assert_eq!(scene[grandchild].parent(), Some(child));
+        let mut roots: Vec<NodeId> = scene.roots().collect();
+        roots.sort_by_key(|id| id.index);
+        assert_eq!(roots, [root, child]);
+    }
+}
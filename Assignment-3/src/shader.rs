@@ -1,18 +1,80 @@
-use gl;
 use std::{
     ptr,
     str,
+    fmt,
     ffi::CString,
     path::Path,
+    time::SystemTime,
+    collections::{BTreeMap, HashMap},
 };
 
+mod preprocessor;
+
+// Preprocessor macros by name, each with the value it is defined as, which may be empty. Kept sorted, so that the same
+// defines always give the same source and can be used as a key
+pub type Defines = BTreeMap<String, String>;
+
 pub struct Shader {
     pub program_id: u32,
+    // The files attached to the builder, one for each stage of the program
+    stage_files: Vec<String>,
+    defines: Defines,
+    // Those files and everything they include, to rebuild the program when they change
+    source_files: Vec<WatchedFile>,
 }
 
 pub struct ShaderBuilder {
     program_id: u32,
     shaders: Vec::<u32>,
+    // Where the attached shaders came from, for error messages
+    source_names: Vec<String>,
+    stage_files: Vec<String>,
+    defines: Defines,
+    // The files that were read along the way
+    source_files: Vec<WatchedFile>,
+}
+
+// A file and when it was last modified, as far as we know. Files that cannot be read have no modification time
+#[derive(Clone, Debug, PartialEq)]
+struct WatchedFile {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn new(path: &str) -> WatchedFile {
+        WatchedFile { path: path.to_string(), modified: modified_time(path) }
+    }
+
+    // Whether the file was modified, created or removed since the last call
+    fn has_changed(&mut self) -> bool {
+        let modified = modified_time(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Reads a file for the preprocessor, watching it from then on
+fn read_watched(source_files: &mut Vec<WatchedFile>, file: &str) -> std::io::Result<String> {
+    // Noted before reading, so that a change made while reading is seen on the next check
+    if !source_files.iter().any(|watched| watched.path == file) {
+        source_files.push(WatchedFile::new(file));
+    }
+    std::fs::read_to_string(file)
+}
+
+// Every file the preprocessor reads for the given stages, or tries to, however far it gets with each of them
+fn watched_sources(stage_files: &[String]) -> Vec<WatchedFile> {
+    let mut source_files = vec![];
+    for path in stage_files {
+        let _ = preprocessor::preprocess(path, |file| read_watched(&mut source_files, file));
+    }
+    source_files
 }
 
 #[allow(dead_code)]
@@ -24,9 +86,37 @@ pub enum ShaderType {
     Geometry,
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: String, error: std::io::Error },
+    UnknownExtension { path: String },
+    // The log is the whole info log of the shader, as the driver wrote it
+    Compile { source_name: String, log: String },
+    Link { source_names: Vec<String>, log: String },
+    // A malformed or cyclic #include, at the given line of source_name
+    Preprocess { source_name: String, line: usize, message: String },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            ShaderError::Io { path, error } => write!(f, "Failed to read shader source {}: {}", path, error),
+            ShaderError::UnknownExtension { path } =>
+                write!(f, "Shader {} should end in .vert, .frag, .tcs, .tes or .geom", path),
+            ShaderError::Compile { source_name, log } => write!(f, "Failed to compile shader {}:\n{}", source_name, log),
+            ShaderError::Link { source_names, log } =>
+                write!(f, "Failed to link shader program from {}:\n{}", source_names.join(", "), log),
+            ShaderError::Preprocess { source_name, line, message } =>
+                write!(f, "Failed to preprocess shader {}:{}: {}", source_name, line, message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
@@ -37,105 +127,344 @@ impl Into<gl::types::GLenum> for ShaderType {
 }
 
 impl ShaderType {
-    fn from_ext(ext: &std::ffi::OsStr) -> Result<ShaderType, String> {
-        match ext.to_str().expect("Failed to read extension") {
-            "vert" => { Ok(ShaderType::Vertex) },
-            "frag" => { Ok(ShaderType::Fragment) },
-            "tcs"  => { Ok(ShaderType::TessellationControl) },
-            "tes"  => { Ok(ShaderType::TessellationEvaluation) },
-            "geom" => { Ok(ShaderType::Geometry) },
-            e => { Err(e.to_string()) },
+    fn from_ext(ext: &std::ffi::OsStr) -> Option<ShaderType> {
+        match ext.to_str()? {
+            "vert" => { Some(ShaderType::Vertex) },
+            "frag" => { Some(ShaderType::Fragment) },
+            "tcs"  => { Some(ShaderType::TessellationControl) },
+            "tes"  => { Some(ShaderType::TessellationEvaluation) },
+            "geom" => { Some(ShaderType::Geometry) },
+            _ => { None },
         }
     }
 }
 
 impl ShaderBuilder {
-    pub unsafe fn new() -> ShaderBuilder {
+    // The program is only created when linking, so a builder that fails before that never touches GL
+    pub fn new() -> ShaderBuilder {
         ShaderBuilder {
-            program_id: gl::CreateProgram(),
+            program_id: 0,
             shaders: vec![],
+            source_names: vec![],
+            stage_files: vec![],
+            defines: Defines::new(),
+            source_files: vec![],
         }
     }
 
-    pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
+    // Defines name as value in every file attached after this. Pass an empty value to only define it
+    #[allow(dead_code)]
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // Adds all of defines, like `define` does for each of them
+    pub fn defines(mut self, defines: &Defines) -> ShaderBuilder {
+        self.defines.extend(defines.iter().map(|(name, value)| (name.clone(), value.clone())));
+        self
+    }
+
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> Result<ShaderBuilder, ShaderError> {
         let path = Path::new(shader_path);
-        if let Some(extension) = path.extension() {
-            let shader_type = ShaderType::from_ext(extension)
-                .expect("Failed to parse file extension.");
-            let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
-        } else {
-            panic!("Failed to read extension of file with path: {}", shader_path);
-        }
+        let shader_type = path.extension()
+            .and_then(ShaderType::from_ext)
+            .ok_or_else(|| ShaderError::UnknownExtension { path: shader_path.to_string() })?;
+        let source_files = &mut self.source_files;
+        let mut preprocessed = preprocessor::preprocess(shader_path, |file| read_watched(source_files, file))?;
+        preprocessed.inject_defines(&self.defines);
+        self.stage_files.push(shader_path.to_string());
+        self.compile_named_shader(&preprocessed.source, shader_type, shader_path).map_err(|error| match error {
+            ShaderError::Compile { source_name, log } => ShaderError::Compile { source_name, log: preprocessed.map_log(&log) },
+            error => error,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub unsafe fn compile_shader(self, shader_src: &str, shader_type: ShaderType) -> Result<ShaderBuilder, ShaderError> {
+        self.compile_named_shader(shader_src, shader_type, "<inline source>")
     }
 
-    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
+    unsafe fn compile_named_shader(mut self, shader_src: &str, shader_type: ShaderType, source_name: &str)
+        -> Result<ShaderBuilder, ShaderError>
+    {
         let shader = gl::CreateShader(shader_type.into());
+        // GL reads the source up to the first nul, so anything after one would be ignored anyway
+        let shader_src = shader_src.split('\0').next().unwrap_or_default();
         let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
         gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
         gl::CompileShader(shader);
 
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
-        }
-
+        // Hand the shader to the builder either way, so that dropping it cleans up
         self.shaders.push(shader);
+        self.source_names.push(source_name.to_string());
+        self.check_shader_errors(shader, source_name)?;
 
-        self
+        Ok(self)
     }
 
-    unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
+    unsafe fn check_shader_errors(&self, shader_id: u32, source_name: &str) -> Result<(), ShaderError> {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
         gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
+            let mut log_length = 0;
+            gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut log_length);
+            let mut info_log = vec![0u8; log_length.max(1) as usize];
             gl::GetShaderInfoLog(
                 shader_id,
-                512,
+                info_log.len() as i32,
                 ptr::null_mut(),
                 info_log.as_mut_ptr() as *mut gl::types::GLchar,
             );
-            println!("ERROR::Shader Compilation Failed!\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+            return Err(ShaderError::Compile { source_name: source_name.to_string(), log: info_log_to_string(&info_log) });
         }
-        true
+        Ok(())
     }
 
-    unsafe fn check_linker_errors(&self) -> bool {
+    unsafe fn check_linker_errors(&self) -> Result<(), ShaderError> {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
         gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
+            let mut log_length = 0;
+            gl::GetProgramiv(self.program_id, gl::INFO_LOG_LENGTH, &mut log_length);
+            let mut info_log = vec![0u8; log_length.max(1) as usize];
             gl::GetProgramInfoLog(
                 self.program_id,
-                512,
+                info_log.len() as i32,
                 ptr::null_mut(),
                 info_log.as_mut_ptr() as *mut gl::types::GLchar,
             );
-            println!("ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+            return Err(ShaderError::Link { source_names: self.source_names.clone(), log: info_log_to_string(&info_log) });
         }
-        true
+        Ok(())
     }
 
-    pub unsafe fn link(self) -> Shader {
+    pub unsafe fn link(mut self) -> Result<Shader, ShaderError> {
+        self.program_id = gl::CreateProgram();
         for &shader in &self.shaders {
             gl::AttachShader(self.program_id, shader);
         }
         gl::LinkProgram(self.program_id);
+        self.check_linker_errors()?;
 
-        // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+        // The program keeps what it needs of the shaders, and is no longer the builder's to delete
+        let program_id = std::mem::replace(&mut self.program_id, 0);
+        Ok(Shader {
+            program_id,
+            stage_files: std::mem::take(&mut self.stage_files),
+            defines: std::mem::take(&mut self.defines),
+            source_files: std::mem::take(&mut self.source_files),
+        })
+    }
+}
 
-        for &shader in &self.shaders {
-            gl::DeleteShader(shader);
+impl Shader {
+    // Rebuilds the program from its files if any of them changed since it was built or last checked, and returns
+    // whether it did. The new program takes the place of the old one under the same program_id field, so it must be
+    // bound again and have its uniforms set again. If the new sources fail, the old program stays as it was.
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, ShaderError> {
+        // Check every file, so that none of them reports the same change twice
+        let mut changed = false;
+        for file in &mut self.source_files {
+            changed |= file.has_changed();
+        }
+        if !changed {
+            return Ok(false);
         }
 
-        Shader {
-            program_id: self.program_id
+        let rebuilt = match self.rebuild() {
+            Ok(rebuilt) => rebuilt,
+            Err(error) => {
+                // The failed builder took its files with it, so find them again. Whatever the broken sources newly
+                // include must be watched too, or fixing it would never bring about another reload
+                self.source_files = watched_sources(&self.stage_files);
+                return Err(error);
+            },
+        };
+        gl::DeleteProgram(self.program_id);
+        self.program_id = rebuilt.program_id;
+        // The includes may have changed along with the files
+        self.source_files = rebuilt.source_files;
+        Ok(true)
+    }
+
+    unsafe fn rebuild(&self) -> Result<Shader, ShaderError> {
+        let mut builder = ShaderBuilder::new().defines(&self.defines);
+        for path in &self.stage_files {
+            builder = builder.attach_file(path)?;
+        }
+        builder.link()
+    }
+}
+
+// Programs built from the same files with different defines, built the first time they are asked for
+pub struct ShaderVariants {
+    stage_files: Vec<String>,
+    variants: HashMap<Defines, Shader>,
+}
+
+impl ShaderVariants {
+    pub fn new(stage_files: &[&str]) -> ShaderVariants {
+        ShaderVariants { stage_files: stage_files.iter().map(|path| path.to_string()).collect(), variants: HashMap::new() }
+    }
+
+    // The program for defines, building it if this is the first time. A variant that fails to build is not kept, so
+    // asking again after fixing the sources builds it again
+    pub unsafe fn get(&mut self, defines: &Defines) -> Result<&Shader, ShaderError> {
+        if !self.variants.contains_key(defines) {
+            let mut builder = ShaderBuilder::new().defines(defines);
+            for path in &self.stage_files {
+                builder = builder.attach_file(path)?;
+            }
+            self.variants.insert(defines.clone(), builder.link()?);
         }
+        Ok(&self.variants[defines])
+    }
+
+    // Reloads every variant built so far like `Shader::reload_if_changed`, and returns whether any of them was
+    // rebuilt. All of them are tried even if one fails, and the first error is returned. The variants share their
+    // sources, so they are likely to fail for the same reason anyway.
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, ShaderError> {
+        let mut reloaded = false;
+        let mut first_error = None;
+        for shader in self.variants.values_mut() {
+            match shader.reload_if_changed() {
+                Ok(changed) => reloaded |= changed,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                },
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(reloaded),
+        }
+    }
+}
+
+// A builder that never made it to a linked program, or an error, leaves nothing behind
+impl Drop for ShaderBuilder {
+    fn drop(&mut self) {
+        unsafe {
+            for &shader in &self.shaders {
+                gl::DeleteShader(shader);
+            }
+            if self.program_id != 0 {
+                gl::DeleteProgram(self.program_id);
+            }
+        }
+    }
+}
+
+// Info logs are nul terminated, and some drivers end them with a newline as well
+fn info_log_to_string(info_log: &[u8]) -> String {
+    let end = info_log.iter().position(|&byte| byte == 0).unwrap_or(info_log.len());
+    String::from_utf8_lossy(&info_log[..end]).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_name_the_source_and_keep_the_whole_log() {
+        assert!(matches!(ShaderType::from_ext("frag".as_ref()), Some(ShaderType::Fragment)));
+        assert!(ShaderType::from_ext("glsl".as_ref()).is_none());
+
+        let log = "0(12) : error C1008: undefined variable \"normal\"\n".repeat(20);
+        let mut info_log = log.clone().into_bytes();
+        info_log.push(0);
+        let error = ShaderError::Compile { source_name: "shaders/simple.frag".to_string(), log: info_log_to_string(&info_log) };
+        let message = error.to_string();
+        assert!(message.starts_with("Failed to compile shader shaders/simple.frag:\n0(12) : error"));
+        assert!(message.len() > 512);
+        assert!(message.ends_with(log.trim_end()));
+    }
+
+    #[test]
+    fn watched_files_report_each_change_once() {
+        let path = std::env::temp_dir().join("gloom_shader_watch_test.frag");
+        std::fs::write(&path, "void main() {}").unwrap();
+        let set_modified = |seconds: u64| {
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds)).unwrap();
+        };
+        set_modified(1_000);
+        let mut watched = WatchedFile::new(path.to_str().unwrap());
+        assert!(!watched.has_changed());
+
+        set_modified(2_000);
+        assert!(watched.has_changed());
+        assert!(!watched.has_changed());
+
+        // Removing the file is a change too, as is bringing it back
+        std::fs::remove_file(&path).unwrap();
+        assert!(watched.has_changed());
+        assert!(!watched.has_changed());
+        std::fs::write(&path, "void main() {}").unwrap();
+        assert!(watched.has_changed());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn attaching_bad_files_fails_before_compiling() {
+        let directory = std::env::temp_dir().join("gloom_shader_attach_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        std::fs::write(path("cycle.frag"), "#version 430 core\n#include \"cycle.glsl\"\nvoid main() {}\n").unwrap();
+        std::fs::write(path("cycle.glsl"), "#include \"cycle.frag\"\n").unwrap();
+        std::fs::write(path("shader.txt"), "void main() {}\n").unwrap();
+
+        // None of these get as far as GL, so no context is needed
+        let attach = |name: &str| unsafe { ShaderBuilder::new().attach_file(&path(name)) }.err();
+        match attach("missing.frag") {
+            Some(ShaderError::Io { path: error_path, .. }) => assert_eq!(error_path, path("missing.frag")),
+            _ => panic!("Expected an IO error"),
+        }
+        match attach("shader.txt") {
+            Some(ShaderError::UnknownExtension { path: error_path }) => assert_eq!(error_path, path("shader.txt")),
+            _ => panic!("Expected an unknown extension error"),
+        }
+        match attach("cycle.frag") {
+            Some(ShaderError::Preprocess { source_name, line, message }) => {
+                assert!(source_name.ends_with("cycle.glsl"), "{}", source_name);
+                assert_eq!(line, 1);
+                assert!(message.contains("includes itself"), "{}", message);
+            },
+            _ => panic!("Expected a preprocessing error"),
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn files_included_by_a_failed_reload_are_watched() {
+        let directory = std::env::temp_dir().join("gloom_shader_reload_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+        let set_modified = |name: &str, seconds: u64| {
+            let file = std::fs::File::options().write(true).open(path(name)).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds)).unwrap();
+        };
+        let _ = std::fs::remove_file(path("lighting.glsl"));
+        std::fs::write(path("shader.frag"), "#version 430 core\nvoid main() {}\n").unwrap();
+        set_modified("shader.frag", 1_000);
+        let mut shader = Shader {
+            program_id: 0,
+            stage_files: vec![path("shader.frag")],
+            defines: Defines::new(),
+            source_files: vec![WatchedFile::new(&path("shader.frag"))],
+        };
+
+        // Including a file that is not there yet fails before GL, so no context is needed
+        std::fs::write(path("shader.frag"), "#version 430 core\n#include \"lighting.glsl\"\nvoid main() {}\n").unwrap();
+        set_modified("shader.frag", 2_000);
+        assert!(matches!(unsafe { shader.reload_if_changed() }, Err(ShaderError::Io { .. })));
+        assert!(shader.source_files.iter().any(|watched| watched.path == path("lighting.glsl")));
+        assert!(matches!(unsafe { shader.reload_if_changed() }, Ok(false)));
+
+        // Creating the file is noticed, and this one fails too, for including itself
+        std::fs::write(path("lighting.glsl"), "#include \"lighting.glsl\"\n").unwrap();
+        assert!(matches!(unsafe { shader.reload_if_changed() }, Err(ShaderError::Preprocess { .. })));
+        std::fs::remove_dir_all(&directory).unwrap();
     }
 }
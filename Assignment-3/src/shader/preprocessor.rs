@@ -0,0 +1,259 @@
+use std::path::{Component, Path, PathBuf};
+
+use super::{Defines, ShaderError};
+
+// Stands in for a file name in error messages about the lines that `inject_defines` adds
+const DEFINES_SOURCE_NAME: &str = "<defines>";
+
+// Where a line of preprocessed source came from. Lines count from 1, like compilers count them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SourceLine {
+    // Index into the files of the preprocessed source
+    file: usize,
+    line: usize,
+}
+
+// Shader source with every `#include "file"` replaced by the contents of that file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preprocessed {
+    pub source: String,
+    // Every file that was read, the shader itself first
+    pub files: Vec<String>,
+    // Where each line of source came from
+    lines: Vec<SourceLine>,
+}
+
+impl Preprocessed {
+    // File and line that line number `line` of the preprocessed source came from
+    pub fn original_line(&self, line: usize) -> Option<(&str, usize)> {
+        let source_line = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[source_line.file], source_line.line))
+    }
+
+    // Rewrites the locations at the start of compiler messages into the file and line they came from. Drivers write
+    // them as "0(12)" (NVIDIA), "0:12(5)" (Mesa) or "ERROR: 0:12:" (AMD and Intel), where 0 is the source string and
+    // 12 the line. Messages that do not look like any of those are left alone.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines().map(|message| self.map_log_message(message)).collect::<Vec<_>>().join("\n")
+    }
+
+    // Adds a #define for each of defines right after the #version line, which must come before anything else, or at
+    // the very start if there is none. Defines without a value are only defined.
+    pub fn inject_defines(&mut self, defines: &Defines) {
+        if defines.is_empty() {
+            return;
+        }
+        let file = match self.files.iter().position(|file| file == DEFINES_SOURCE_NAME) {
+            Some(file) => file,
+            None => {
+                self.files.push(DEFINES_SOURCE_NAME.to_string());
+                self.files.len() - 1
+            },
+        };
+        let version_line = self.source.lines().position(|line| line.trim_start().starts_with("#version"));
+        let insert_at = version_line.map_or(0, |line| line + 1);
+
+        let mut lines = self.source.lines().map(|line| line.to_string()).collect::<Vec<_>>();
+        let mut injected_lines = vec![];
+        for (index, (name, value)) in defines.iter().enumerate() {
+            lines.insert(insert_at + index, format!("#define {} {}", name, value).trim_end().to_string());
+            injected_lines.push(SourceLine { file, line: index + 1 });
+        }
+        self.lines.splice(insert_at..insert_at, injected_lines);
+        self.source = lines.iter().map(|line| format!("{}\n", line)).collect();
+    }
+
+    fn map_log_message(&self, message: &str) -> String {
+        let prefix_length = ["ERROR: ", "WARNING: "].iter()
+            .find(|prefix| message.starts_with(*prefix))
+            .map_or(0, |prefix| prefix.len());
+        let location = &message[prefix_length..];
+        let string_digits = count_digits(location);
+        let (open, close) = match location[string_digits..].chars().next() {
+            Some('(') => ("(", ")"),
+            Some(':') => (":", ""),
+            _ => return message.to_string(),
+        };
+        let after_open = &location[string_digits + 1..];
+        let line_digits = count_digits(after_open);
+        if string_digits == 0 || line_digits == 0 || !after_open[line_digits..].starts_with(close) {
+            return message.to_string();
+        }
+        let end = prefix_length + string_digits + 1 + line_digits + close.len();
+        match after_open[..line_digits].parse().ok().and_then(|line| self.original_line(line)) {
+            Some((file, line)) => format!("{}{}{}{}{}{}", &message[..prefix_length], file, open, line, close, &message[end..]),
+            None => message.to_string(),
+        }
+    }
+}
+
+fn count_digits(text: &str) -> usize {
+    text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len())
+}
+
+// Reads the shader at path with read and resolves its includes, recursively. Included paths are relative to the file
+// that includes them. A file may be included more than once, but not from within itself.
+pub fn preprocess<F>(path: &str, mut read: F) -> Result<Preprocessed, ShaderError>
+    where F: FnMut(&str) -> std::io::Result<String>
+{
+    let mut preprocessed = Preprocessed { source: String::new(), files: vec![], lines: vec![] };
+    let mut include_stack = vec![];
+    expand(&normalize(Path::new(path)), &mut read, &mut include_stack, &mut preprocessed)?;
+    Ok(preprocessed)
+}
+
+fn expand<F>(path: &str, read: &mut F, include_stack: &mut Vec<String>, preprocessed: &mut Preprocessed)
+    -> Result<(), ShaderError>
+    where F: FnMut(&str) -> std::io::Result<String>
+{
+    let text = read(path).map_err(|error| ShaderError::Io { path: path.to_string(), error })?;
+    let file = match preprocessed.files.iter().position(|file| file == path) {
+        Some(file) => file,
+        None => {
+            preprocessed.files.push(path.to_string());
+            preprocessed.files.len() - 1
+        },
+    };
+    include_stack.push(path.to_string());
+
+    for (index, text_line) in text.lines().enumerate() {
+        let include_error = |message: String| ShaderError::Preprocess { source_name: path.to_string(), line: index + 1, message };
+        match parse_include(text_line) {
+            Some(Ok(name)) => {
+                let included = normalize(&Path::new(path).parent().unwrap_or_else(|| Path::new("")).join(name));
+                if include_stack.contains(&included) {
+                    let cycle = include_stack.iter().skip_while(|file| **file != included).cloned().collect::<Vec<_>>();
+                    return Err(include_error(format!("{} includes itself through {} -> {}", included, cycle.join(" -> "), included)));
+                }
+                expand(&included, read, include_stack, preprocessed)?;
+            },
+            Some(Err(message)) => return Err(include_error(message)),
+            None => {
+                preprocessed.source.push_str(text_line);
+                preprocessed.source.push('\n');
+                preprocessed.lines.push(SourceLine { file, line: index + 1 });
+            },
+        }
+    }
+
+    include_stack.pop();
+    Ok(())
+}
+
+// The quoted file name of an include directive, an error for a malformed one, and None for any other line
+fn parse_include(line: &str) -> Option<Result<&str, String>> {
+    let directive = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?;
+    // Some other directive that starts with the same letters
+    if directive.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let name = directive.trim_start()
+        .strip_prefix('"')
+        .and_then(|rest| rest.find('"').map(|end| (&rest[..end], rest[end + 1..].trim())));
+    match name {
+        Some((name, rest)) if !name.is_empty() && (rest.is_empty() || rest.starts_with("//")) => Some(Ok(name)),
+        _ => Some(Err(format!("expected #include \"file\", found {}", line.trim()))),
+    }
+}
+
+// Resolves "." and ".." without touching the file system, so that two spellings of a path compare equal
+fn normalize(path: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn reader<'a>(files: &'a HashMap<&str, &str>) -> impl FnMut(&str) -> std::io::Result<String> + 'a {
+        move |path| files.get(path)
+            .map(|text| text.to_string())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, path.to_string()))
+    }
+
+    #[test]
+    fn includes_are_resolved_relative_to_the_including_file() {
+        let files: HashMap<&str, &str> = [
+            ("shaders/simple.frag", "#version 430 core\n#include \"lib/lighting.glsl\"\nvoid main() {}\n"),
+            ("shaders/lib/lighting.glsl", "  #  include \"../common.glsl\"  // uniforms\nvec3 light;\n"),
+            ("shaders/common.glsl", "uniform vec3 camera_position;\n"),
+        ].iter().cloned().collect();
+        let preprocessed = preprocess("./shaders/simple.frag", reader(&files)).unwrap();
+        assert_eq!(preprocessed.source, "#version 430 core\nuniform vec3 camera_position;\nvec3 light;\nvoid main() {}\n");
+        assert_eq!(preprocessed.files, vec!["shaders/simple.frag", "shaders/lib/lighting.glsl", "shaders/common.glsl"]);
+        assert_eq!(preprocessed.original_line(1), Some(("shaders/simple.frag", 1)));
+        assert_eq!(preprocessed.original_line(2), Some(("shaders/common.glsl", 1)));
+        assert_eq!(preprocessed.original_line(3), Some(("shaders/lib/lighting.glsl", 2)));
+        assert_eq!(preprocessed.original_line(4), Some(("shaders/simple.frag", 3)));
+        assert_eq!(preprocessed.original_line(0), None);
+        assert_eq!(preprocessed.original_line(5), None);
+    }
+
+    #[test]
+    fn cycles_and_bad_directives_are_errors() {
+        let files: HashMap<&str, &str> = [
+            ("a.frag", "#version 430 core\n#include \"b.glsl\"\n"),
+            ("b.glsl", "\n#include \"./a.frag\"\n"),
+            ("c.frag", "#include <b.glsl>\n"),
+            ("d.frag", "#include \"missing.glsl\"\n#includes are not a thing\n"),
+        ].iter().cloned().collect();
+        match preprocess("a.frag", reader(&files)) {
+            Err(ShaderError::Preprocess { source_name, line, message }) => {
+                assert_eq!((source_name.as_str(), line), ("b.glsl", 2));
+                assert_eq!(message, "a.frag includes itself through a.frag -> b.glsl -> a.frag");
+            },
+            _ => panic!("expected an include cycle"),
+        }
+        assert!(matches!(preprocess("c.frag", reader(&files)), Err(ShaderError::Preprocess { line: 1, .. })));
+        assert!(matches!(preprocess("d.frag", reader(&files)), Err(ShaderError::Io { path, .. }) if path == "missing.glsl"));
+        assert_eq!(parse_include("#includes are not a thing"), None);
+    }
+
+    #[test]
+    fn defines_go_right_after_the_version() {
+        let files: HashMap<&str, &str> = [
+            ("main.frag", "// Lighting\n#version 430 core\nvoid main() {}\n"),
+            ("bare.frag", "void main() {}\n"),
+        ].iter().cloned().collect();
+        let defines: Defines = [("SHOW_NORMALS", ""), ("LIGHTS", "4")].iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let mut preprocessed = preprocess("main.frag", reader(&files)).unwrap();
+        preprocessed.inject_defines(&defines);
+        assert_eq!(preprocessed.source, "// Lighting\n#version 430 core\n#define LIGHTS 4\n#define SHOW_NORMALS\nvoid main() {}\n");
+        assert_eq!(preprocessed.original_line(3), Some(("<defines>", 1)));
+        assert_eq!(preprocessed.original_line(5), Some(("main.frag", 3)));
+        assert_eq!(preprocessed.map_log("0(5) : error"), "main.frag(3) : error");
+
+        let mut bare = preprocess("bare.frag", reader(&files)).unwrap();
+        bare.inject_defines(&defines);
+        assert_eq!(bare.source, "#define LIGHTS 4\n#define SHOW_NORMALS\nvoid main() {}\n");
+        assert_eq!(bare.original_line(3), Some(("bare.frag", 1)));
+    }
+
+    #[test]
+    fn compiler_logs_point_at_the_original_lines() {
+        let files: HashMap<&str, &str> = [
+            ("main.frag", "#version 430 core\n#include \"light.glsl\"\nvoid main() {}\n"),
+            ("light.glsl", "vec3 light;\nvec3 broken\n"),
+        ].iter().cloned().collect();
+        let preprocessed = preprocess("main.frag", reader(&files)).unwrap();
+        let log = "0(3) : error C0000: syntax error\n0:4(12): error: syntax error\nERROR: 0:1: '' : bad version\n\
+                   0(99) : error C0000: past the end\nsomething else";
+        assert_eq!(preprocessed.map_log(log), "light.glsl(2) : error C0000: syntax error\n\
+                                               main.frag:3(12): error: syntax error\n\
+                                               ERROR: main.frag:1: '' : bad version\n\
+                                               0(99) : error C0000: past the end\nsomething else");
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt;
+
+use gl::types::{GLenum, GLint};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl From<Wrap> for GLenum {
+    fn from(wrap: Wrap) -> GLenum {
+        match wrap {
+            Wrap::Repeat         => { gl::REPEAT          },
+            Wrap::MirroredRepeat => { gl::MIRRORED_REPEAT },
+            Wrap::ClampToEdge    => { gl::CLAMP_TO_EDGE   },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl From<Filter> for GLenum {
+    fn from(filter: Filter) -> GLenum {
+        match filter {
+            Filter::Nearest => { gl::NEAREST },
+            Filter::Linear  => { gl::LINEAR  },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureSettings {
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    // Generate mipmaps, and blend between them when the texture is minified
+    pub mipmaps: bool,
+}
+
+impl Default for TextureSettings {
+    fn default() -> TextureSettings {
+        TextureSettings {
+            wrap_s: Wrap::Repeat,
+            wrap_t: Wrap::Repeat,
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmaps: true,
+        }
+    }
+}
+
+impl TextureSettings {
+    // GL_TEXTURE_MIN_FILTER, which also decides how mipmap levels are picked and blended
+    fn min_filter_enum(&self) -> GLenum {
+        match (self.mipmaps, self.min_filter) {
+            (false, filter) => filter.into(),
+            (true, Filter::Nearest) => gl::NEAREST_MIPMAP_NEAREST,
+            (true, Filter::Linear) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TextureError {
+    pub path: String,
+    pub error: image::ImageError,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to load texture {}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+// Decoded RGBA8 pixels, with the bottom row first as OpenGL and OBJ texture coordinates expect
+pub struct TextureImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl TextureImage {
+    pub fn load(path: &str) -> Result<TextureImage, TextureError> {
+        image::open(path)
+            .map(TextureImage::from_image)
+            .map_err(|error| TextureError { path: path.to_string(), error })
+    }
+
+    pub fn from_image(image: image::DynamicImage) -> TextureImage {
+        let rgba = image.flipv().into_rgba8();
+        TextureImage {
+            width: rgba.width(),
+            height: rgba.height(),
+            pixels: rgba.into_raw(),
+        }
+    }
+
+    pub unsafe fn upload(&self, settings: &TextureSettings) -> Texture {
+        let mut id: u32 = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        // Rows of RGBA8 pixels are always 4 byte aligned, but be explicit about it
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as GLint,
+            self.width as i32,
+            self.height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            self.pixels.as_ptr() as *const c_void
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, GLenum::from(settings.wrap_s) as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, GLenum::from(settings.wrap_t) as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, settings.min_filter_enum() as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, GLenum::from(settings.mag_filter) as GLint);
+        if settings.mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        Texture { id, width: self.width, height: self.height }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Texture {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Texture {
+    #[allow(dead_code)]
+    pub unsafe fn load(path: &str, settings: &TextureSettings) -> Result<Texture, TextureError> {
+        Ok(TextureImage::load(path)?.upload(settings))
+    }
+}
+
+// Image files are only decoded and uploaded once for each settings, however many nodes are textured with them
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<(String, TextureSettings), Texture>,
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache::default()
+    }
+
+    pub unsafe fn get_or_load(&mut self, path: &str, settings: &TextureSettings) -> Result<Texture, TextureError> {
+        let key = (path.to_string(), *settings);
+        if let Some(&texture) = self.textures.get(&key) {
+            return Ok(texture);
+        }
+        let texture = TextureImage::load(path)?.upload(settings);
+        self.textures.insert(key, texture);
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_flipped_and_expanded_to_rgba() {
+        // Top row red, bottom row blue
+        let image = image::RgbImage::from_fn(2, 2, |_, y| {
+            if y == 0 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+        });
+        let texture = TextureImage::from_image(image::DynamicImage::ImageRgb8(image));
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.pixels.len(), 2 * 2 * 4);
+        assert_eq!(texture.pixels[..4], [0, 0, 255, 255]);
+        assert_eq!(texture.pixels[8..12], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn png_files_are_decoded() {
+        let path = std::env::temp_dir().join("gloom_texture_test.png");
+        let image = image::GrayImage::from_fn(3, 1, |x, _| image::Luma([x as u8 * 100]));
+        image.save(&path).unwrap();
+        let texture = TextureImage::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((texture.width, texture.height), (3, 1));
+        assert_eq!(texture.pixels[8..12], [200, 200, 200, 255]);
+
+        match TextureImage::load("./does/not/exist.png") {
+            Err(TextureError { path, .. }) => assert_eq!(path, "./does/not/exist.png"),
+            Ok(_) => panic!("Expected texture error"),
+        }
+    }
+
+    #[test]
+    fn min_filter_blends_mipmaps_only_when_they_exist() {
+        let mut settings = TextureSettings::default();
+        assert_eq!(settings.min_filter_enum(), gl::LINEAR_MIPMAP_LINEAR);
+        settings.min_filter = Filter::Nearest;
+        assert_eq!(settings.min_filter_enum(), gl::NEAREST_MIPMAP_NEAREST);
+        settings.mipmaps = false;
+        assert_eq!(settings.min_filter_enum(), gl::NEAREST);
+    }
+}